@@ -0,0 +1,83 @@
+use clap::Parser;
+use miette::Result;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chat_server::bot::Bot;
+
+/// Example helper bot for standups and reminders
+#[derive(Parser)]
+struct Args {
+    /// Server port
+    #[arg(
+        short,
+        long,
+        default_value_t = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 8080))
+    )]
+    socket_addr: SocketAddr,
+
+    /// User name of the bot
+    #[arg(short, long, default_value = "helper-bot")]
+    name: String,
+
+    /// Chat rooms the bot sits in, created if they do not exist
+    #[arg(short, long, default_value = "general", value_delimiter = ',')]
+    rooms: Vec<String>,
+}
+
+/// Entry point
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let rooms: Vec<&str> = args.rooms.iter().map(String::as_str).collect();
+
+    // Standup notes per user, shared by all rooms
+    let notes: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+
+    let bot = Bot::new(args.name)
+        .command(&rooms, "help", |ctx| {
+            ctx.reply("commands: !standup [note], !remind <seconds> <text>, !ping");
+        })
+        .command(&rooms, "ping", |ctx| ctx.reply("pong"))
+        .command(&rooms, "standup", move |ctx| {
+            let mut notes = notes.lock().unwrap();
+
+            if ctx.args.is_empty() {
+                let summary: Vec<String> = notes
+                    .iter()
+                    .map(|(user, note)| format!("{user}: {note}"))
+                    .collect();
+
+                if summary.is_empty() {
+                    ctx.reply("No standup notes yet");
+                } else {
+                    ctx.reply(format!("Standup: {}", summary.join(" | ")));
+                }
+            } else {
+                notes.insert(ctx.sender().to_string(), ctx.args.join(" "));
+                ctx.reply(format!("Noted, {}", ctx.sender()));
+            }
+        })
+        .command(&rooms, "remind", |ctx| {
+            let seconds = ctx.args.first().and_then(|s| s.parse::<u64>().ok());
+
+            match (seconds, ctx.args.len()) {
+                (Some(seconds), 2..) => {
+                    let text = ctx.args[1..].join(" ");
+                    let reminder = format!("{}: reminder, {text}", ctx.sender());
+                    ctx.reply_after(Duration::from_secs(seconds), reminder);
+                    ctx.reply(format!("I will remind you in {seconds} seconds"));
+                }
+                _ => ctx.reply("usage: !remind <seconds> <text>"),
+            }
+        })
+        .on_message(
+            &rooms,
+            |msg| msg.content.to_lowercase().contains("build failed"),
+            |ctx| ctx.reply("Build failure noticed, check the CI logs"),
+        );
+
+    bot.run(args.socket_addr).await
+}
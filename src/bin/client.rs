@@ -1,6 +1,9 @@
 use clap::Parser;
 use miette::Result;
+use std::env;
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::PathBuf;
+use std::time::Duration;
 
 use chat_server::client::Client;
 
@@ -14,13 +17,24 @@ struct Args {
         default_value_t = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 8080))
     )]
     socket_addr: SocketAddr,
+    /// Minutes without input after which you are marked as away, 0 to never
+    #[arg(long, default_value_t = 10)]
+    away_after: u64,
+    /// File the key for encrypted direct messages is kept in, defaults to ~/.chat-client-key
+    #[arg(long)]
+    key_file: Option<PathBuf>,
 }
 
 /// Entry point
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let client = Client::setup(args.socket_addr).await?;
+    let away_after = Some(Duration::from_secs(args.away_after * 60)).filter(|d| !d.is_zero());
+    // Without a home directory the key only lasts as long as the client runs
+    let key_file = args
+        .key_file
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".chat-client-key")));
+    let client = Client::setup(args.socket_addr, away_after, key_file.as_deref()).await?;
     client.run().await?;
 
     Ok(())
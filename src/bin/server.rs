@@ -1,8 +1,10 @@
-use clap::Parser;
-use miette::Result;
+use clap::{Parser, ValueEnum};
+use miette::{miette, Result};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::path::PathBuf;
+use tracing::Level;
 
-use chat_server::server::Server;
+use chat_server::server::{Config, Server};
 
 /// A chat server written in Rust
 #[derive(Parser)]
@@ -14,13 +16,105 @@ struct Args {
         default_value_t = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 8080))
     )]
     socket_addr: SocketAddr,
+
+    /// Path to a TOML config file, reloaded on SIGHUP
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Address to serve prometheus metrics on, disabled if not given
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Path of a unix socket to serve the admin interface on, disabled if not given
+    #[arg(long)]
+    admin_socket: Option<PathBuf>,
+
+    /// Address to accept links from the other instances of a cluster on, disabled if not given,
+    /// links are not encrypted
+    #[arg(long)]
+    cluster_addr: Option<SocketAddr>,
+
+    /// Cluster address of another instance to relay rooms and membership to, repeat for every
+    /// other instance
+    #[arg(long)]
+    peer: Vec<SocketAddr>,
+
+    /// Address to accept IRC clients on, disabled if not given
+    #[arg(long)]
+    irc_addr: Option<SocketAddr>,
+
+    /// Most verbose level that is logged
+    #[arg(long, default_value_t = Level::INFO)]
+    log_level: Level,
+
+    /// Format of the log output
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+/// Output format of the logs
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Installs the global log subscriber
+fn setup_logging(level: Level, format: LogFormat) {
+    let subscriber = tracing_subscriber::fmt().with_max_level(level);
+
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().with_current_span(true).init(),
+    }
 }
 
 /// Entry point
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
-    let server = Server::new(args.socket_addr);
+    setup_logging(args.log_level, args.log_format);
+
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    // Without a secret any host that reaches the cluster address could link to the cluster
+    let clustered = args.cluster_addr.is_some() || !args.peer.is_empty();
+    let has_secret = matches!(config.cluster_secret.as_deref(), Some(secret) if !secret.is_empty());
+    if clustered && !has_secret {
+        return Err(miette!(
+            "Set a cluster_secret in the config to use --cluster-addr or --peer"
+        ));
+    }
+
+    let mut server = match args.config {
+        Some(path) => Server::new(args.socket_addr, config)?.config_path(path),
+        None => Server::new(args.socket_addr, config)?,
+    };
+
+    if let Some(addr) = args.metrics_addr {
+        server = server.metrics_addr(addr);
+    }
+
+    if let Some(path) = args.admin_socket {
+        server = server.admin_socket(path);
+    }
+
+    if let Some(addr) = args.cluster_addr {
+        server = server.cluster_addr(addr);
+    }
+
+    for addr in args.peer {
+        server = server.peer(addr);
+    }
+
+    if let Some(addr) = args.irc_addr {
+        server = server.irc_addr(addr);
+    }
     server.run().await;
 
     Ok(())
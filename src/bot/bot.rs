@@ -0,0 +1,188 @@
+use futures_util::StreamExt;
+use miette::{miette, IntoDiagnostic, Result};
+use std::{collections::BTreeSet, net::SocketAddr, sync::Arc};
+use tokio::{select, sync::mpsc, task::JoinSet, time};
+
+use crate::bot::context::Context;
+use crate::client::ChatClient;
+use crate::server::communication::{
+    error::ErrorCode,
+    server::{NewMessageRequest, ServerEnvelope, ServerMessage},
+};
+
+/// Function that handles a message that triggered it
+type Handler = Box<dyn Fn(&mut Context) + Send + Sync>;
+
+/// Function that decides whether a message triggers a handler
+type Predicate = Box<dyn Fn(&NewMessageRequest) -> bool + Send + Sync>;
+
+/// What triggers a handler
+enum Trigger {
+    /// A message starting with `!<name>`
+    Command(String),
+    /// A message for which the predicate holds
+    Predicate(Predicate),
+}
+
+/// A handler and the rooms and messages it is registered for
+struct Registration {
+    rooms: Vec<String>,
+    trigger: Trigger,
+    handler: Handler,
+}
+
+impl Registration {
+    /// Returns the arguments if the message in the given room triggers this registration
+    fn matches<'a>(&self, room: &str, message: &'a NewMessageRequest) -> Option<Vec<&'a str>> {
+        if !self.rooms.iter().any(|r| r == room) {
+            return None;
+        }
+
+        match &self.trigger {
+            Trigger::Command(name) => {
+                let mut words = message.content.split_whitespace();
+                let command = words.next()?.strip_prefix('!')?;
+
+                (command == name).then(|| words.collect())
+            }
+            Trigger::Predicate(predicate) => predicate(message).then(Vec::new),
+        }
+    }
+}
+
+/// Bot that sits in chat rooms and answers messages with registered handlers
+pub struct Bot {
+    /// User name of the bot
+    name: String,
+    /// All registered handlers
+    registrations: Vec<Registration>,
+}
+
+impl Bot {
+    /// Creates a bot without any handlers
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Registers a handler for `!<name>` commands in the given rooms
+    pub fn command<F>(mut self, rooms: &[&str], name: &str, handler: F) -> Self
+    where
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.registrations.push(Registration {
+            rooms: rooms.iter().map(|room| room.to_string()).collect(),
+            trigger: Trigger::Command(name.to_string()),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Registers a handler for messages in the given rooms that match a predicate
+    pub fn on_message<P, F>(mut self, rooms: &[&str], predicate: P, handler: F) -> Self
+    where
+        P: Fn(&NewMessageRequest) -> bool + Send + Sync + 'static,
+        F: Fn(&mut Context) + Send + Sync + 'static,
+    {
+        self.registrations.push(Registration {
+            rooms: rooms.iter().map(|room| room.to_string()).collect(),
+            trigger: Trigger::Predicate(Box::new(predicate)),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Runs all handlers triggered by a message, returns their replies
+    fn dispatch(&self, room: &str, message: &NewMessageRequest) -> Vec<(time::Duration, String)> {
+        let mut replies = Vec::new();
+
+        for registration in &self.registrations {
+            if let Some(args) = registration.matches(room, message) {
+                let mut context = Context::new(room, message, args);
+                (registration.handler)(&mut context);
+                replies.append(&mut context.replies);
+            }
+        }
+
+        replies
+    }
+
+    /// Serves a single chat room over its own connection
+    async fn run_room(self: Arc<Self>, socket_addr: SocketAddr, room: String) -> Result<()> {
+        let (mut chat, mut events) = ChatClient::connect(socket_addr).await?;
+        chat.change_name(self.name.clone()).await?;
+        let mut join_request = chat.join(room.clone()).await?;
+
+        let (reply_send, mut reply_recv) = mpsc::unbounded_channel();
+
+        loop {
+            select! {
+                event = events.next() => {
+                    let Some(event) = event else {
+                        return Err(miette!("Server closed the connection"));
+                    };
+                    let ServerEnvelope { request_id, message } = event?;
+
+                    match message {
+                        ServerMessage::NewMessage(message) => {
+                            for (delay, content) in self.dispatch(&room, &message) {
+                                // Immediate replies keep the order of the messages
+                                if delay.is_zero() {
+                                    let _ = reply_send.send(content);
+                                    continue;
+                                }
+
+                                let reply_send = reply_send.clone();
+                                tokio::spawn(async move {
+                                    time::sleep(delay).await;
+                                    let _ = reply_send.send(content);
+                                });
+                            }
+                        }
+                        // Create rooms the bot is registered for but that do not exist yet
+                        ServerMessage::Err(error)
+                            if request_id == Some(join_request)
+                                && error.code == ErrorCode::RoomNotFound =>
+                        {
+                            chat.make_room(room.clone()).await?;
+                            join_request = chat.join(room.clone()).await?;
+                        }
+                        _ => {}
+                    }
+                }
+                Some(content) = reply_recv.recv() => {
+                    chat.send(content).await?;
+                }
+            }
+        }
+    }
+
+    /// Connects to the server and runs the bot in all rooms it has handlers for
+    pub async fn run(self, socket_addr: SocketAddr) -> Result<()> {
+        let rooms: BTreeSet<String> = self
+            .registrations
+            .iter()
+            .flat_map(|registration| registration.rooms.iter().cloned())
+            .collect();
+
+        if rooms.is_empty() {
+            return Err(miette!("Bot has no handlers registered"));
+        }
+
+        let bot = Arc::new(self);
+        let mut tasks = JoinSet::new();
+
+        for room in rooms {
+            tasks.spawn(bot.clone().run_room(socket_addr, room));
+        }
+
+        // The bot stops as soon as one of its rooms fails
+        while let Some(result) = tasks.join_next().await {
+            result.into_diagnostic()??;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use crate::server::communication::server::NewMessageRequest;
+
+/// Message that triggered a bot handler, used to reply to it
+pub struct Context<'a> {
+    /// Chat room the message was sent in
+    pub room: &'a str,
+    /// The message itself
+    pub message: &'a NewMessageRequest,
+    /// Words following the command, empty for message predicates
+    pub args: Vec<&'a str>,
+    /// Replies and the delay after which they are sent
+    pub(crate) replies: Vec<(Duration, String)>,
+}
+
+impl<'a> Context<'a> {
+    /// Creates a context for a message in a chat room
+    pub(crate) fn new(room: &'a str, message: &'a NewMessageRequest, args: Vec<&'a str>) -> Self {
+        Self {
+            room,
+            message,
+            args,
+            replies: Vec::new(),
+        }
+    }
+
+    /// Name of the user that sent the message
+    pub fn sender(&self) -> &str {
+        &self.message.user_name
+    }
+
+    /// Replies in the chat room of the message
+    pub fn reply(&mut self, content: impl Into<String>) {
+        self.reply_after(Duration::ZERO, content);
+    }
+
+    /// Replies in the chat room of the message after a delay, e.g. for reminders
+    pub fn reply_after(&mut self, delay: Duration, content: impl Into<String>) {
+        self.replies.push((delay, content.into()));
+    }
+}
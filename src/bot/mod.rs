@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+mod bot;
+mod context;
+
+pub use bot::Bot;
+pub use context::Context;
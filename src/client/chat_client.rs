@@ -0,0 +1,371 @@
+use bincode::{deserialize, serialize};
+use futures_util::{
+    ready,
+    stream::{SplitSink, SplitStream},
+    SinkExt, Stream, StreamExt,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use std::{
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use crate::server::communication::{
+    checksum,
+    client::{
+        AdminLoginRequest, AnnounceRequest, AttachmentId, ChangeNameRequest, ClientEnvelope,
+        ClientMakeChatRoomRequest, ClientMessage, CredentialsRequest, DirectMessageRequest,
+        DownloadRequest, FetchHistoryRequest, FetchKeyRequest, FetchThreadRequest, FilterSettings,
+        FinishUploadRequest, IgnoreRequest, JoinChatRoomRequest, MarkReadRequest, MessageId,
+        Presence, PublicKeyBytes, PublishKeyRequest, ReactRequest, RequestId, ReviewHeldRequest,
+        SealedContent, SearchRequest, SendMessageRequest, SetFilterRequest, SetPresenceRequest,
+        StartUploadRequest, UploadChunkRequest, UploadId, WhoRequest, CHUNK_SIZE,
+    },
+    server::ServerEnvelope,
+};
+
+/// Websocket shorthand
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Write part of the websocket shorthand
+type WebSocketWrite = SplitSink<WebSocket, Message>;
+
+/// Receiving part of the websocket shorthand
+type WebSocketRecv = SplitStream<WebSocket>;
+
+/// Stream of messages sent by the server, ends when the connection is closed
+pub struct Events {
+    recv: WebSocketRecv,
+}
+
+impl Stream for Events {
+    type Item = Result<ServerEnvelope>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let msg = match ready!(self.recv.poll_next_unpin(cx)) {
+                Some(Ok(Message::Binary(bytes))) => deserialize(&bytes).into_diagnostic(),
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(None),
+                // Pings and other control messages are handled by tungstenite
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => Err(err).into_diagnostic(),
+            };
+
+            return Poll::Ready(Some(msg));
+        }
+    }
+}
+
+/// Connection to a chat server, sends requests while the server messages arrive through [`Events`]
+pub struct ChatClient {
+    /// For writing messages to the server
+    write: WebSocketWrite,
+    /// ID of the next request sent to the server
+    next_request_id: RequestId,
+    /// ID of the next upload
+    next_upload_id: UploadId,
+}
+
+impl ChatClient {
+    /// Connects to the server at the given address
+    pub async fn connect(socket_addr: SocketAddr) -> Result<(Self, Events)> {
+        let (ws, _) = connect_async(&format!("ws://{}", socket_addr))
+            .await
+            .map_err(|err| miette!("Failed to connect to server {err:?}"))?;
+        let (write, recv) = ws.split();
+
+        let client = ChatClient {
+            write,
+            next_request_id: 0,
+            next_upload_id: 0,
+        };
+
+        Ok((client, Events { recv }))
+    }
+
+    /// Sends a request to the server, returns its ID to match the reply
+    pub async fn request(&mut self, message: ClientMessage) -> Result<RequestId> {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+
+        let envelope = ClientEnvelope::new(Some(request_id), message);
+        let binary = serialize(&envelope).into_diagnostic()?;
+        self.write
+            .send(Message::Binary(binary))
+            .await
+            .into_diagnostic()?;
+
+        Ok(request_id)
+    }
+
+    /// Sends a chat message to the current chat room
+    pub async fn send(&mut self, content: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::SendMessage(SendMessageRequest::new(content.into()));
+        self.request(message).await
+    }
+
+    /// Replies to a message in the current chat room
+    pub async fn reply(
+        &mut self,
+        reply_to: MessageId,
+        content: impl Into<String>,
+    ) -> Result<RequestId> {
+        let message =
+            ClientMessage::SendMessage(SendMessageRequest::reply(reply_to, content.into()));
+        self.request(message).await
+    }
+
+    /// Requests the thread a message in the current chat room is part of
+    pub async fn thread(&mut self, id: MessageId) -> Result<RequestId> {
+        let message = ClientMessage::FetchThread(FetchThreadRequest::new(id));
+        self.request(message).await
+    }
+
+    /// Reacts to a message in the current chat room with an emoji
+    pub async fn react(
+        &mut self,
+        message_id: MessageId,
+        emoji: impl Into<String>,
+    ) -> Result<RequestId> {
+        let message = ClientMessage::React(ReactRequest::new(message_id, emoji.into()));
+        self.request(message).await
+    }
+
+    /// Removes a reaction to a message in the current chat room
+    pub async fn unreact(
+        &mut self,
+        message_id: MessageId,
+        emoji: impl Into<String>,
+    ) -> Result<RequestId> {
+        let message = ClientMessage::Unreact(ReactRequest::new(message_id, emoji.into()));
+        self.request(message).await
+    }
+
+    /// Tells the current chat room that the user is typing, the server drops too frequent ones
+    pub async fn typing(&mut self) -> Result<RequestId> {
+        self.request(ClientMessage::Typing()).await
+    }
+
+    /// Sets whether the user is available, with a message shown next to it
+    pub async fn set_presence(
+        &mut self,
+        presence: Presence,
+        status: Option<String>,
+    ) -> Result<RequestId> {
+        let message = ClientMessage::SetPresence(SetPresenceRequest::new(presence, status));
+        self.request(message).await
+    }
+
+    /// Requests the members of a chat room with their presence
+    pub async fn who(&mut self, room: impl Into<String>) -> Result<RequestId> {
+        self.request(ClientMessage::Who(WhoRequest::new(room.into())))
+            .await
+    }
+
+    /// Uploads a file in chunks, returns its attachment ID and file name once all chunks are
+    /// sent, errors of the upload arrive through [`Events`]
+    pub async fn upload(&mut self, path: impl AsRef<Path>) -> Result<(AttachmentId, String)> {
+        let path = path.as_ref();
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|err| miette!("Could not read {}: {err}", path.display()))?;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or(miette!("{} is not a file", path.display()))?;
+
+        let upload_id = self.next_upload_id;
+        self.next_upload_id += 1;
+        let id = checksum(&data);
+
+        let start =
+            StartUploadRequest::new(upload_id, file_name.clone(), data.len() as u64, id.clone());
+        self.request(ClientMessage::StartUpload(start)).await?;
+
+        for (index, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            let offset = (index * CHUNK_SIZE) as u64;
+            let chunk = UploadChunkRequest::new(upload_id, offset, chunk.to_vec());
+            self.request(ClientMessage::UploadChunk(chunk)).await?;
+        }
+
+        let finish = FinishUploadRequest::new(upload_id);
+        self.request(ClientMessage::FinishUpload(finish)).await?;
+
+        Ok((id, file_name))
+    }
+
+    /// Sends a chat message with a file uploaded before to the current chat room
+    pub async fn attach(
+        &mut self,
+        attachment: AttachmentId,
+        content: impl Into<String>,
+    ) -> Result<RequestId> {
+        let message =
+            ClientMessage::SendMessage(SendMessageRequest::attach(attachment, content.into()));
+        self.request(message).await
+    }
+
+    /// Requests the content of an attachment, it arrives in chunks through [`Events`]
+    pub async fn download(&mut self, attachment: AttachmentId) -> Result<RequestId> {
+        let message = ClientMessage::Download(DownloadRequest::new(attachment));
+        self.request(message).await
+    }
+
+    /// Creates a new chat room
+    pub async fn make_room(&mut self, name: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::MakeChatRoom(ClientMakeChatRoomRequest::new(name.into()));
+        self.request(message).await
+    }
+
+    /// Joins a chat room, leaving the current one
+    pub async fn join(&mut self, name: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::JoinChatRoom(JoinChatRoomRequest::new(name.into()));
+        self.request(message).await
+    }
+
+    /// Leaves the current chat room without joining another one
+    pub async fn leave(&mut self) -> Result<RequestId> {
+        self.request(ClientMessage::LeaveChatRoom()).await
+    }
+
+    /// Requests the list of chat rooms
+    pub async fn list(&mut self) -> Result<RequestId> {
+        self.request(ClientMessage::ListChatRooms()).await
+    }
+
+    /// Changes the user name
+    pub async fn change_name(&mut self, new_name: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::ChangeName(ChangeNameRequest::new(new_name.into()));
+        self.request(message).await
+    }
+
+    /// Registers a name with a password, so nobody else can use it
+    pub async fn register(
+        &mut self,
+        name: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<RequestId> {
+        let request = CredentialsRequest::new(name.into(), password.into());
+        self.request(ClientMessage::Register(request)).await
+    }
+
+    /// Logs in to a registered name, queued direct messages arrive afterwards
+    pub async fn login(
+        &mut self,
+        name: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<RequestId> {
+        let request = CredentialsRequest::new(name.into(), password.into());
+        self.request(ClientMessage::Login(request)).await
+    }
+
+    /// Sends a direct message to a user, queued if the user is registered but offline
+    pub async fn direct_message(
+        &mut self,
+        to: impl Into<String>,
+        content: impl Into<String>,
+    ) -> Result<RequestId> {
+        let message =
+            ClientMessage::DirectMessage(DirectMessageRequest::new(to.into(), content.into()));
+        self.request(message).await
+    }
+
+    /// Sends a direct message whose content is encrypted for the receiving user
+    pub async fn sealed_direct_message(
+        &mut self,
+        to: impl Into<String>,
+        sealed: SealedContent,
+    ) -> Result<RequestId> {
+        let message = ClientMessage::DirectMessage(DirectMessageRequest::sealed(to.into(), sealed));
+        self.request(message).await
+    }
+
+    /// Publishes the key others encrypt their direct messages with, requires a login
+    pub async fn publish_key(&mut self, public_key: PublicKeyBytes) -> Result<RequestId> {
+        let message = ClientMessage::PublishKey(PublishKeyRequest::new(public_key));
+        self.request(message).await
+    }
+
+    /// Requests the published key of a user
+    pub async fn fetch_key(&mut self, user: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::FetchKey(FetchKeyRequest::new(user.into()));
+        self.request(message).await
+    }
+
+    /// Stops delivering the messages of a user, requires a login
+    pub async fn ignore(&mut self, user: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::Ignore(IgnoreRequest::new(user.into()));
+        self.request(message).await
+    }
+
+    /// Delivers the messages of an ignored user again, requires a login
+    pub async fn unignore(&mut self, user: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::Unignore(IgnoreRequest::new(user.into()));
+        self.request(message).await
+    }
+
+    /// Requests the users whose messages are not delivered
+    pub async fn ignored(&mut self) -> Result<RequestId> {
+        self.request(ClientMessage::ListIgnored()).await
+    }
+
+    /// Marks a chat room as read up to its latest message, requires a login
+    pub async fn mark_read(&mut self, room: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::MarkRead(MarkReadRequest::new(room.into()));
+        self.request(message).await
+    }
+
+    /// Requests a page of the history of a chat room, the latest messages if no ID is given
+    pub async fn history(
+        &mut self,
+        room: impl Into<String>,
+        before_id: Option<MessageId>,
+        limit: usize,
+    ) -> Result<RequestId> {
+        let request = FetchHistoryRequest::new(room.into(), before_id, limit);
+        self.request(ClientMessage::FetchHistory(request)).await
+    }
+
+    /// Searches the history of a chat room, the request also selects author, time and page
+    pub async fn search(&mut self, request: SearchRequest) -> Result<RequestId> {
+        self.request(ClientMessage::Search(request)).await
+    }
+
+    /// Logs in as admin
+    pub async fn admin_login(&mut self, password: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::AdminLogin(AdminLoginRequest::new(password.into()));
+        self.request(message).await
+    }
+
+    /// Sends an announcement to all clients, requires admin rights
+    pub async fn announce(&mut self, content: impl Into<String>) -> Result<RequestId> {
+        let message = ClientMessage::Announce(AnnounceRequest::new(content.into()));
+        self.request(message).await
+    }
+
+    /// Sets or removes the content filter of the current chat room, requires ownership
+    pub async fn set_filter(&mut self, filter: Option<FilterSettings>) -> Result<RequestId> {
+        let message = ClientMessage::SetFilter(SetFilterRequest::new(filter));
+        self.request(message).await
+    }
+
+    /// Requests the messages held by the filter of the current chat room
+    pub async fn held(&mut self) -> Result<RequestId> {
+        self.request(ClientMessage::ListHeld()).await
+    }
+
+    /// Broadcasts or discards a held message in the current chat room
+    pub async fn review_held(&mut self, id: u64, approve: bool) -> Result<RequestId> {
+        let message = ClientMessage::ReviewHeld(ReviewHeldRequest::new(id, approve));
+        self.request(message).await
+    }
+
+    /// Closes the connection to the server
+    pub async fn close(&mut self) -> Result<()> {
+        self.write.close().await.into_diagnostic()
+    }
+}
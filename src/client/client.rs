@@ -1,108 +1,699 @@
-use bincode::{deserialize, serialize};
-use futures_util::{
-    stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt,
+use futures_util::StreamExt;
+use miette::Result;
+use std::{
+    collections::HashMap,
+    fs, mem,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
-use miette::{miette, IntoDiagnostic, Result};
-use std::net::SocketAddr;
-use tokio::{net::TcpStream, select};
-use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio::{select, time};
 
+use crate::client::chat_client::{ChatClient, Events};
 use crate::client::frontend::{Command, Frontend};
-use crate::server::communication::server::ServerMessage;
-
-/// Websocket shorthand
-type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
-
-/// Write part of the websocket shorthand
-type WebSocketWrite = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
-
-/// Receiving part of the websocket shorthand
-type WebSocketRecv = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
-
-/// Connects client to server
-async fn connect_to_server(socket_addr: SocketAddr) -> Result<WebSocket> {
-    println!("Connecting to server ...");
+use crate::client::keys::{self, KeyPair, KnownKeys};
+use crate::server::communication::{
+    checksum,
+    client::{
+        AttachmentId, ClientMakeChatRoomRequest, ClientMessage, DirectMessageRequest,
+        FetchHistoryRequest, JoinChatRoomRequest, MarkReadRequest, Presence,
+        PublicKeyBytes, RequestId, RequestKind, SearchRequest, SendMessageRequest,
+        SetPresenceRequest, WhoRequest,
+    },
+    error::{ErrorCode, ServerError},
+    server::{
+        DirectMessageResponse, DownloadChunkResponse, PublicKeyResponse, ServerEnvelope,
+        ServerMessage,
+    },
+};
 
-    match connect_async(&format!("ws://{}", socket_addr)).await {
-        Ok((ws, _)) => {
-            println!("Connected to server");
-            Ok(ws)
-        }
-        Err(err) => Err(miette!("Failed to connect to server {err:?}")),
-    }
+/// Attachment that is being downloaded
+struct Download {
+    /// File the attachment is saved to
+    path: PathBuf,
+    /// Request the download was started with, errors about it end the download
+    request: RequestId,
+    /// Chunks received so far
+    data: Vec<u8>,
 }
 
 /// Client that connects to the server
 pub struct Client {
     /// Frontend that reads and prints to terminal
     frontend: Frontend,
-    /// For writing messages to the server
-    write: WebSocketWrite,
-    /// For receiving messages from the server
-    recv: WebSocketRecv,
+    /// Connection to the server
+    chat: ChatClient,
+    /// Messages from the server
+    events: Events,
+    /// ID of the last join request that has not been answered yet
+    join_request: Option<RequestId>,
+    /// Chat room the client was in before its last join request
+    previous_chatroom: Option<String>,
+    /// Missing chat room the user was offered to create
+    create_offer: Option<String>,
+    /// Last search, to fetch its next page with `/more`
+    search: Option<SearchRequest>,
+    /// Last history page, to fetch the one before it with `/older`
+    history: Option<FetchHistoryRequest>,
+    /// Downloads in progress
+    downloads: HashMap<AttachmentId, Download>,
+    /// Time without input after which the user is marked as away, if at all
+    away_after: Option<Duration>,
+    /// When the user last typed something
+    last_input: Instant,
+    /// Whether the user was marked as away for being idle, rather than by choice
+    auto_away: bool,
+    /// Key pair direct messages to the user are encrypted for
+    keys: KeyPair,
+    /// Public keys of other users seen so far
+    known_keys: KnownKeys,
+    /// Direct messages waiting for the key of their recipient, by recipient
+    pending_direct_messages: HashMap<String, Vec<String>>,
+    /// Received direct messages waiting for the published key of their sender, by sender
+    unverified_direct_messages: HashMap<String, Vec<DirectMessageResponse>>,
+    /// Recipient without a key the user was asked to send the pending messages to unencrypted
+    plaintext_offer: Option<String>,
+}
+
+/// Reads a yes or no answer to an offer, none if the input is something else
+fn answer(cmd: &Command) -> Option<bool> {
+    let Command::Request(ClientMessage::SendMessage(SendMessageRequest { content, .. })) = cmd
+    else {
+        return None;
+    };
+
+    match content.to_lowercase().as_str() {
+        "y" | "yes" => Some(true),
+        "n" | "no" => Some(false),
+        _ => None,
+    }
 }
 
 impl Client {
-    /// Instantiates a new client
-    pub async fn setup(socket_addr: SocketAddr) -> Result<Self> {
+    /// Instantiates a new client, which marks the user as away after the given idle time and
+    /// keeps its key for direct messages in the given file, if any
+    pub async fn setup(
+        socket_addr: SocketAddr,
+        away_after: Option<Duration>,
+        key_file: Option<&Path>,
+    ) -> Result<Self> {
         println!("Setting up client...");
 
-        let conn = connect_to_server(socket_addr).await?;
-        let (write, recv) = conn.split();
+        let keys = match key_file {
+            Some(path) => KeyPair::load_or_create(path)?,
+            None => KeyPair::generate(),
+        };
+        let known_keys = KnownKeys::load(key_file)?;
+
+        println!("Connecting to server ...");
+        let (chat, events) = ChatClient::connect(socket_addr).await?;
+        println!("Connected to server");
+
         let frontend = Frontend::new()?;
 
         let client = Client {
             frontend,
-            write,
-            recv,
+            chat,
+            events,
+            join_request: None,
+            previous_chatroom: None,
+            create_offer: None,
+            search: None,
+            history: None,
+            downloads: HashMap::new(),
+            away_after,
+            last_input: Instant::now(),
+            auto_away: false,
+            keys,
+            known_keys,
+            pending_direct_messages: HashMap::new(),
+            unverified_direct_messages: HashMap::new(),
+            plaintext_offer: None,
         };
 
         Ok(client)
     }
 
     /// Handles messages from the server
-    fn handle_server_msg(&mut self, msg: Vec<u8>) -> Result<()> {
-        let message: ServerMessage = deserialize(&msg).into_diagnostic()?;
+    async fn handle_server_msg(&mut self, envelope: ServerEnvelope) -> Result<()> {
+        let ServerEnvelope {
+            request_id,
+            message,
+        } = envelope;
 
         match message {
             ServerMessage::NewMessage(m) => {
-                self.frontend.print_message(m.content, m.user_name)?;
+                self.frontend.stop_typing(&m.user_name);
+                if m.mentioned {
+                    self.frontend.unread_mentions += 1;
+                }
+                self.frontend.print_chat_message(&m)?;
             }
             ServerMessage::JoinedChatRoom(m) => {
+                if request_id == self.join_request {
+                    self.join_request = None;
+                    self.previous_chatroom = None;
+                }
+
                 self.frontend.current_chatroom = m.name;
+                self.frontend.clear_typing();
                 self.frontend.print_prompt()?;
             }
             ServerMessage::ListChatRooms(m) => {
-                self.frontend.print_rooms(m.names)?;
+                self.frontend.print_rooms(m.names, &m.unread)?;
                 self.frontend.print_prompt()?;
             }
-            ServerMessage::Err(error) => {
-                self.frontend.print_err(&error)?;
+            ServerMessage::LeftChatRoom(m) => {
+                self.frontend.current_chatroom = String::from("None");
+                self.frontend.clear_typing();
+                self.frontend
+                    .print_message(format!("Left chat room {}", m.name), "Server".to_string())?;
+            }
+            ServerMessage::Announcement(m) => {
+                self.frontend.print_announcement(&m.content)?;
+            }
+            ServerMessage::Motd(m) => {
+                self.frontend.print_motd(&m.content)?;
+            }
+            ServerMessage::Ack(m) => match m.request {
+                RequestKind::MakeChatRoom => self
+                    .frontend
+                    .print_message("Created chat room".to_string(), "Server".to_string())?,
+                RequestKind::ChangeName => self
+                    .frontend
+                    .print_message("Changed name".to_string(), "Server".to_string())?,
+                RequestKind::AdminLogin => self
+                    .frontend
+                    .print_message("Logged in as admin".to_string(), "Server".to_string())?,
+                RequestKind::SetFilter => self
+                    .frontend
+                    .print_message("Updated room filter".to_string(), "Server".to_string())?,
+                RequestKind::ReviewHeld => self
+                    .frontend
+                    .print_message("Reviewed held message".to_string(), "Server".to_string())?,
+                RequestKind::Register => self
+                    .frontend
+                    .print_message("Registered name".to_string(), "Server".to_string())?,
+                // Others can only encrypt direct messages once they know the key
+                RequestKind::Login => {
+                    self.frontend
+                        .print_message("Logged in".to_string(), "Server".to_string())?;
+                    self.chat.publish_key(self.keys.public_key()).await?;
+                }
+                RequestKind::PublishKey => self.frontend.print_message(
+                    format!(
+                        "Published your key, its fingerprint is {}",
+                        keys::fingerprint(&self.keys.public_key())
+                    ),
+                    "Server".to_string(),
+                )?,
+                RequestKind::MarkRead => self
+                    .frontend
+                    .print_message("Marked room as read".to_string(), "Server".to_string())?,
+                RequestKind::Ignore => self
+                    .frontend
+                    .print_message("Ignoring user".to_string(), "Server".to_string())?,
+                RequestKind::Unignore => self
+                    .frontend
+                    .print_message("Stopped ignoring user".to_string(), "Server".to_string())?,
+                // The status line shows the new presence
+                RequestKind::SetPresence => self.frontend.print_prompt()?,
+                _ => {}
+            },
+            ServerMessage::SearchResults(m) => {
+                self.frontend.print_search_results(&m)?;
+
+                let next = m.next_before_id;
+                self.search =
+                    self.search
+                        .take()
+                        .filter(|_| next.is_some())
+                        .map(|search| SearchRequest {
+                            before_id: next,
+                            ..search
+                        });
+            }
+            ServerMessage::History(m) => {
+                self.frontend.print_history(&m)?;
+
+                let next = m.next_before_id;
+                self.history = self
+                    .history
+                    .take()
+                    .filter(|_| next.is_some())
+                    .map(|history| FetchHistoryRequest {
+                        before_id: next,
+                        ..history
+                    });
+            }
+            ServerMessage::DownloadChunk(m) => self.handle_download_chunk(m)?,
+            ServerMessage::Typing(m) => self.frontend.show_typing(m.user_name)?,
+            ServerMessage::Presence(m) => self.frontend.print_presence(&m)?,
+            ServerMessage::Who(m) => self.frontend.print_who(&m)?,
+            ServerMessage::Ignored(m) => self.frontend.print_ignored(&m.users)?,
+            ServerMessage::DirectMessage(m) => self.handle_direct_message(m).await?,
+            ServerMessage::PublicKey(m) => self.handle_public_key(m).await?,
+            ServerMessage::Reactions(m) => {
+                self.frontend.print_reactions(m.message_id, &m.reactions)?;
+            }
+            ServerMessage::Thread(m) => {
+                self.frontend.print_thread(&m.room, &m.messages)?;
+            }
+            ServerMessage::HeldMessages(m) => {
+                self.frontend.print_held(&m.room, m.messages)?;
+            }
+            ServerMessage::Err(error) => self.handle_server_err(request_id, error).await?,
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the key of a user was already requested and not answered yet
+    fn awaits_key(&self, user: &str) -> bool {
+        self.pending_direct_messages.contains_key(user)
+            || self.unverified_direct_messages.contains_key(user)
+    }
+
+    /// Decrypts a direct message if it is sealed and prints it, fetches the published key of
+    /// the sender first if it is not the one seen before
+    async fn handle_direct_message(&mut self, mut message: DirectMessageResponse) -> Result<()> {
+        let Some(sealed) = &message.sealed else {
+            return self.frontend.print_direct_message(&message, false);
+        };
+
+        match self.keys.open(sealed) {
+            Ok(content) => message.content = content,
+            Err(_) => {
+                let error = format!("could not decrypt a direct message from {}", message.from);
+                return self.frontend.print_err(&error);
+            }
+        }
+
+        // Anyone can encrypt with a key of their own, only the published one proves the sender
+        let sender_key = sealed.sender_key;
+        if self.known_keys.get(&message.from) == Some(&sender_key) {
+            return self.print_sealed_direct_message(&message, Some(sender_key));
+        }
+
+        let from = message.from.clone();
+        let fetch = !self.awaits_key(&from);
+        self.unverified_direct_messages
+            .entry(from.clone())
+            .or_default()
+            .push(message);
+        if fetch {
+            self.chat.fetch_key(from).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Prints a decrypted direct message, marked as encrypted only if the sender used the key
+    /// they published
+    fn print_sealed_direct_message(
+        &self,
+        message: &DirectMessageResponse,
+        published: Option<PublicKeyBytes>,
+    ) -> Result<()> {
+        let verified = message
+            .sealed
+            .as_ref()
+            .is_some_and(|sealed| Some(sealed.sender_key) == published);
+        if !verified {
+            let warning = match published {
+                Some(_) => "was encrypted with a key they did not publish",
+                None => "cannot be verified, they have not published a key",
+            };
+            self.frontend.print_err(&format!(
+                "the direct message from {} {warning}",
+                message.from
+            ))?;
+        }
+
+        self.frontend.print_direct_message(message, verified)
+    }
+
+    /// Prints the received direct messages that waited for the key of their sender
+    fn release_unverified(&mut self, user: &str, published: Option<PublicKeyBytes>) -> Result<()> {
+        for message in self
+            .unverified_direct_messages
+            .remove(user)
+            .unwrap_or_default()
+        {
+            self.print_sealed_direct_message(&message, published)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remembers the key of another user, prints the direct messages from them and sends the
+    /// direct messages to them that waited for it
+    async fn handle_public_key(&mut self, response: PublicKeyResponse) -> Result<()> {
+        let PublicKeyResponse { user, public_key } = response;
+
+        let changed = match self.known_keys.pin(&user, public_key) {
+            Ok(changed) => changed,
+            Err(report) => {
+                self.frontend.print_err(&report.to_string())?;
+                false
+            }
+        };
+        self.frontend
+            .print_fingerprint(&user, &keys::fingerprint(&public_key), changed)?;
+        self.release_unverified(&user, Some(public_key))?;
+
+        for content in self
+            .pending_direct_messages
+            .remove(&user)
+            .unwrap_or_default()
+        {
+            self.send_direct_message(user.clone(), content).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a direct message encrypted for its recipient, fetches the key of the recipient
+    /// first if it is not known yet
+    async fn send_direct_message(&mut self, to: String, content: String) -> Result<()> {
+        let Some(public_key) = self.known_keys.get(&to) else {
+            let fetch = !self.awaits_key(&to);
+            self.pending_direct_messages
+                .entry(to.clone())
+                .or_default()
+                .push(content);
+            if fetch {
+                self.chat.fetch_key(to).await?;
+            }
+
+            return Ok(());
+        };
+
+        match self.keys.seal(public_key, &content) {
+            Ok(sealed) => {
+                self.chat.sealed_direct_message(to, sealed).await?;
+            }
+            Err(report) => self.frontend.print_err(&report.to_string())?,
+        }
+
+        Ok(())
+    }
+
+    /// Collects the chunks of a download, saves the file once it is complete
+    fn handle_download_chunk(&mut self, chunk: DownloadChunkResponse) -> Result<()> {
+        let Some(download) = self.downloads.get_mut(&chunk.attachment_id) else {
+            return Ok(());
+        };
+
+        download.data.extend_from_slice(&chunk.data);
+        if (download.data.len() as u64) < chunk.size && !chunk.data.is_empty() {
+            return Ok(());
+        }
+
+        let Some(download) = self.downloads.remove(&chunk.attachment_id) else {
+            return Ok(());
+        };
+        if checksum(&download.data) != chunk.attachment_id {
+            return self
+                .frontend
+                .print_err("download does not match its checksum");
+        }
+
+        match fs::write(&download.path, &download.data) {
+            Ok(()) => self.frontend.print_message(
+                format!("Saved attachment to {}", download.path.display()),
+                "Server".to_string(),
+            ),
+            Err(err) => self.frontend.print_err(&format!(
+                "could not save {}: {err}",
+                download.path.display()
+            )),
+        }
+    }
+
+    /// Handles errors reported by the server
+    async fn handle_server_err(
+        &mut self,
+        request_id: Option<RequestId>,
+        error: ServerError,
+    ) -> Result<()> {
+        if request_id.is_some() && request_id == self.join_request {
+            self.join_request = None;
+
+            if let Some(previous) = self.previous_chatroom.take() {
+                self.frontend.current_chatroom = previous;
+            }
+        }
+
+        // A refused search has no next page
+        if error.request == Some(RequestKind::Search) {
+            self.search = None;
+        }
+
+        // No chunks follow an error, so a failed download is not waited for anymore
+        if let Some(request_id) = request_id {
+            self.downloads
+                .retain(|_, download| download.request != request_id);
+        }
+
+        match (error.code, error.request, &error.details) {
+            (ErrorCode::RoomNotFound, Some(RequestKind::JoinChatRoom), Some(room)) => {
+                self.frontend
+                    .print_err(&format!("Room {room} does not exist, create it? (y/n)"))?;
+                self.create_offer = Some(room.clone());
+            }
+            // Users without a key, e.g. older clients, only get unencrypted messages if the
+            // user agrees to send them
+            (ErrorCode::KeyNotFound, Some(RequestKind::FetchKey), Some(user))
+                if self.awaits_key(user) =>
+            {
+                self.release_unverified(user, None)?;
+                if !self.pending_direct_messages.contains_key(user) {
+                    return Ok(());
+                }
+
+                self.frontend.print_err(&format!(
+                    "{user} has not published a key, send the message unencrypted? (y/n)"
+                ))?;
+
+                if let Some(previous) = self.plaintext_offer.replace(user.clone()) {
+                    self.discard_direct_messages(&previous)?;
+                }
             }
+            _ => self.frontend.print_err(&error.to_string())?,
         }
 
         Ok(())
     }
 
-    /// Sends a command to the server
-    async fn send_cmd(&mut self, cmd: Command) -> Result<()> {
-        let binary = serialize(&cmd).into_diagnostic()?;
-        self.write
-            .send(Message::Binary(binary))
-            .await
-            .into_diagnostic()?;
+    /// Sends a command to the server, returns the ID of the request
+    async fn send_cmd(&mut self, cmd: ClientMessage) -> Result<RequestId> {
+        self.chat.request(cmd).await
+    }
+
+    /// Sends a join request and shows that the client is connecting
+    async fn send_join(&mut self, cmd: ClientMessage) -> Result<()> {
+        self.join_request = Some(self.send_cmd(cmd).await?);
+
+        let previous = mem::replace(
+            &mut self.frontend.current_chatroom,
+            String::from("Connecting..."),
+        );
+        self.previous_chatroom.get_or_insert(previous);
+
+        Ok(())
+    }
+
+    /// Handles the answer to an offer to create a missing chat room, returns whether it was one
+    async fn handle_create_offer(&mut self, cmd: &Command) -> Result<bool> {
+        let Some(room) = self.create_offer.take() else {
+            return Ok(false);
+        };
+
+        match answer(cmd) {
+            Some(true) => {
+                let make =
+                    ClientMessage::MakeChatRoom(ClientMakeChatRoomRequest::new(room.clone()));
+                self.send_cmd(make).await?;
+
+                let join = ClientMessage::JoinChatRoom(JoinChatRoomRequest::new(room));
+                self.send_join(join).await?;
+
+                Ok(true)
+            }
+            Some(false) => {
+                self.frontend.print_prompt()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Handles the answer to an offer to send direct messages unencrypted, returns whether it
+    /// was one
+    async fn handle_plaintext_offer(&mut self, cmd: &Command) -> Result<bool> {
+        let Some(user) = self.plaintext_offer.take() else {
+            return Ok(false);
+        };
+
+        // Anything but a yes keeps the messages from leaving unencrypted
+        let answer = answer(cmd);
+        if answer != Some(true) {
+            self.discard_direct_messages(&user)?;
+            return Ok(answer.is_some());
+        }
+
+        let pending = self.pending_direct_messages.remove(&user);
+        for content in pending.unwrap_or_default() {
+            self.chat.direct_message(user.clone(), content).await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Drops the direct messages that waited for the key of a recipient
+    fn discard_direct_messages(&mut self, user: &str) -> Result<()> {
+        let pending = self
+            .pending_direct_messages
+            .remove(user)
+            .unwrap_or_default();
+
+        self.frontend.print_message(
+            format!("Discarded {} direct messages to {user}", pending.len()),
+            "Server".to_string(),
+        )
+    }
+
+    /// Sets the presence of the user, shown in the status line
+    async fn set_presence(&mut self, presence: Presence, status: Option<String>) -> Result<()> {
+        self.frontend.presence = presence;
+        self.chat.set_presence(presence, status).await?;
+
+        Ok(())
+    }
+
+    /// Expires typing users and marks the user as away once idle, runs every second
+    async fn tick(&mut self) -> Result<()> {
+        self.frontend.expire_typing()?;
+
+        let idle = self
+            .away_after
+            .is_some_and(|away_after| self.last_input.elapsed() >= away_after);
+        if idle && self.frontend.presence == Presence::Online {
+            self.auto_away = true;
+            self.set_presence(Presence::Away, None).await?;
+        }
 
         Ok(())
     }
 
     /// Handles user commands
     async fn handle_user_cmd(&mut self, cmd: Command) -> Result<()> {
+        self.last_input = Instant::now();
+
+        // Any input brings the user back from being away for idleness
+        if self.auto_away && !matches!(cmd, Command::Request(ClientMessage::SetPresence(_))) {
+            self.set_presence(Presence::Online, None).await?;
+        }
+        self.auto_away = false;
+
+        // Typing the answer to an offer must not drop the offer
+        if let Command::Request(ClientMessage::Typing()) = cmd {
+            self.chat.typing().await?;
+            return Ok(());
+        }
+
+        if self.handle_create_offer(&cmd).await? || self.handle_plaintext_offer(&cmd).await? {
+            return Ok(());
+        }
+
+        let cmd = match cmd {
+            Command::Request(request) => request,
+            Command::Upload { path } => {
+                match self.chat.upload(&path).await {
+                    Ok((id, file_name)) => {
+                        self.chat.attach(id, file_name).await?;
+                    }
+                    Err(report) => self.frontend.print_err(&report.to_string())?,
+                }
+                return Ok(());
+            }
+            Command::Download { id, path } => {
+                let request = self.chat.download(id.clone()).await?;
+                let download = Download {
+                    path,
+                    request,
+                    data: Vec::new(),
+                };
+                self.downloads.insert(id, download);
+                return Ok(());
+            }
+            Command::MoreResults => {
+                match self.search.clone() {
+                    Some(search) => {
+                        self.send_cmd(ClientMessage::Search(search)).await?;
+                    }
+                    None => self.frontend.print_err("no more search results")?,
+                }
+                return Ok(());
+            }
+            Command::History { room, limit } => {
+                let room = room.unwrap_or_else(|| self.frontend.current_chatroom.clone());
+                let history = FetchHistoryRequest::new(room, None, limit);
+                self.history = Some(history.clone());
+                self.send_cmd(ClientMessage::FetchHistory(history)).await?;
+                return Ok(());
+            }
+            Command::OlderHistory => {
+                match self.history.clone() {
+                    Some(history) => {
+                        self.send_cmd(ClientMessage::FetchHistory(history)).await?;
+                    }
+                    None => self.frontend.print_err("no older messages")?,
+                }
+                return Ok(());
+            }
+            Command::MarkRead { room } => {
+                let room = room.unwrap_or_else(|| self.frontend.current_chatroom.clone());
+                self.send_cmd(ClientMessage::MarkRead(MarkReadRequest::new(room)))
+                    .await?;
+                return Ok(());
+            }
+            Command::Who { room } => {
+                let room = room.unwrap_or_else(|| self.frontend.current_chatroom.clone());
+                self.send_cmd(ClientMessage::Who(WhoRequest::new(room)))
+                    .await?;
+                return Ok(());
+            }
+            Command::OwnFingerprint => {
+                self.frontend.print_message(
+                    format!(
+                        "Your key fingerprint is {}",
+                        keys::fingerprint(&self.keys.public_key())
+                    ),
+                    "Server".to_string(),
+                )?;
+                return Ok(());
+            }
+        };
+
         match cmd {
-            Command::Help() => self.frontend.print_help()?,
-            Command::JoinChatRoom(_) => {
-                self.send_cmd(cmd).await?;
-                self.frontend.current_chatroom = String::from("Connecting...");
+            ClientMessage::Help() => self.frontend.print_help()?,
+            ClientMessage::JoinChatRoom(_) => self.send_join(cmd).await?,
+            ClientMessage::Search(mut search) => {
+                if search.room.is_empty() {
+                    search.room = self.frontend.current_chatroom.clone();
+                }
+                self.search = Some(search.clone());
+                self.send_cmd(ClientMessage::Search(search)).await?;
+            }
+            ClientMessage::DirectMessage(DirectMessageRequest {
+                to,
+                content,
+                sealed: None,
+            }) => self.send_direct_message(to, content).await?,
+            ClientMessage::SetPresence(SetPresenceRequest { presence, status }) => {
+                self.set_presence(presence, status).await?;
             }
             _ => {
                 self.send_cmd(cmd).await?;
@@ -114,14 +705,21 @@ impl Client {
 
     /// Starts the client, handles commands and server messages
     pub async fn run(mut self) -> Result<()> {
+        let mut ticks = time::interval(Duration::from_secs(1));
+
         loop {
             select! {
-                Some(Ok(Message::Binary(msg))) = self.recv.next() => {
-                    self.handle_server_msg(msg)?;
-                }
+                event = self.events.next() => match event {
+                    Some(envelope) => self.handle_server_msg(envelope?).await?,
+                    None => {
+                        self.frontend.print_disconnected();
+                        return Ok(());
+                    }
+                },
                 Ok(Some(cmd)) = self.frontend.next() => {
                     self.handle_user_cmd(cmd).await?;
                 },
+                _ = ticks.tick() => self.tick().await?,
             }
         }
     }
@@ -1,17 +1,85 @@
-use crossterm::{cursor, terminal, ExecutableCommand};
+use chrono::{DateTime, Days, Local, NaiveDate, Utc};
+use crossterm::{
+    cursor,
+    event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers},
+    style::Stylize,
+    terminal, ExecutableCommand,
+};
+use futures_util::StreamExt;
 use miette::{miette, IntoDiagnostic, Result};
-use std::io::{self, stdout, Write};
-use std::process;
+use std::collections::BTreeMap;
+use std::io::{self, stdout, IsTerminal, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use std::{mem, process};
 use tokio::io::{AsyncBufReadExt, BufReader, Stdin};
 
-use crate::server::communication::client::{
-    ChangeNameRequest, ClientMakeChatRoomRequest, ClientMessage, JoinChatRoomRequest,
-    SendMessageRequest,
+use crate::server::communication::{
+    client::{
+        AdminLoginRequest, AnnounceRequest, AttachmentId, ChangeNameRequest,
+        ClientMakeChatRoomRequest, ClientMessage, CredentialsRequest, DirectMessageRequest,
+        FetchKeyRequest, FetchThreadRequest, FilterMode, FilterSettings, IgnoreRequest,
+        JoinChatRoomRequest, MessageId, Presence, ReactRequest, ReviewHeldRequest, SearchRequest,
+        SendMessageRequest, SetFilterRequest, SetPresenceRequest,
+    },
+    server::{
+        DirectMessageResponse, HeldMessage, HistoryResponse, NewMessageRequest, PresenceResponse,
+        ReactionCount, SearchResultsResponse, WhoResponse,
+    },
 };
 
+/// Writes text to the terminal, in raw mode a newline does not return the cursor by itself
+fn write_out(text: String) {
+    let text = match terminal::is_raw_mode_enabled() {
+        Ok(true) => text.replace('\n', "\r\n"),
+        _ => text,
+    };
+    let _ = stdout().write_all(text.as_bytes());
+}
+
+/// Like `print!`, but also works while the terminal is in raw mode
+macro_rules! out {
+    ($($arg:tt)*) => {
+        write_out(format!($($arg)*))
+    };
+}
+
+/// Like `println!`, but also works while the terminal is in raw mode
+macro_rules! outln {
+    () => {
+        write_out(String::from("\n"))
+    };
+    ($($arg:tt)*) => {
+        write_out(format!("{}\n", format_args!($($arg)*)))
+    };
+}
+
 /// Prints help message to the terminal
 fn print_help() {
-    println!("usage:\n\t/make <room-name>\tcreate a new chatroom\n\t/join <room-name>\tjoins a chatroom\n\t/list\t\t\tlists all chatrooms\n\t/cname <new-username>\tchanges used name\n\t/exit\t\t\texits the application")
+    outln!("usage:\n\t/make <room-name>\tcreate a new chatroom\n\t/join <room-name>\tjoins a chatroom\n\t/leave\t\t\tleaves the current chatroom\n\t/list\t\t\tlists all chatrooms\n\t/cname <new-username>\tchanges used name\n\t/admin <password>\tlogs in as admin\n\t/announce <message>\tsends an announcement to everyone (admin only)\n\t/filter <reject|mask|hold> <word|/regex/>...\tsets the filter of your room (owner only)\n\t/filter off\t\tremoves the filter of your room\n\t/held\t\t\tlists messages held by the filter\n\t/approve <id>\t\tbroadcasts a held message\n\t/discard <id>\t\tdiscards a held message\n\t/reply <id> <message>\treplies to a message\n\t/thread <id>\t\tshows the thread of a message\n\t/react <id> <emoji>\treacts to a message\n\t/unreact <id> <emoji>\tremoves a reaction\n\t/register <name> <password>\tregisters your name\n\t/login <name> <password>\tlogs in to a registered name\n\t/msg <name> <message>\tsends a direct message\n\t/read [room-name]\tmarks a chatroom as read, defaults to the current one\n\t/search [from:<name>] [since:<yyyy-mm-dd>] [until:<yyyy-mm-dd>] [in:<room-name>] [words]\tsearches the history of a chatroom\n\t/more\t\t\tshows more results of the last search\n\t/history [room-name]\tshows the latest messages of a chatroom, defaults to the current one\n\t/older\t\t\tshows the messages before the last history page\n\t/upload <path>\t\tsends a file to the chatroom\n\t/download <id> <path>\tsaves an attached file\n\t/away [message]\tmarks you as away\n\t/dnd [message]\t\tasks others not to disturb you\n\t/back\t\t\tmarks you as online again\n\t/who [room-name]\tlists the members of a chatroom, defaults to the current one\n\t/ignore <name>\t\tstops showing messages of a user\n\t/unignore <name>\tshows messages of a user again\n\t/ignored\t\tlists the users you ignore\n\t/fingerprint [name]\tshows the key fingerprint of a user to verify direct messages, or your own\n\t/exit\t\t\texits the application")
+}
+
+/// Shortest time between two typing events sent to the server
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+
+/// How long another user is shown as typing after their last typing event
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Switches the terminal to raw mode, so the frontend sees keys while they are typed instead
+/// of whole lines, returns false if stdin is not a terminal
+fn read_single_keys() -> bool {
+    io::stdin().is_terminal() && terminal::enable_raw_mode().is_ok()
+}
+
+/// Gives the terminal back the mode it had before the frontend started
+fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+}
+
+/// Restores the terminal and exits the application
+fn exit() -> ! {
+    restore_terminal();
+    process::exit(0)
 }
 
 /// Crops a given number of characters from the start of a string
@@ -43,8 +111,180 @@ fn flush_io() {
     let _ = io::stdout().flush();
 }
 
-/// Alias
-pub type Command = ClientMessage;
+/// Shortens a message to at most the given number of characters
+fn excerpt(content: &str, max: usize) -> String {
+    if content.chars().count() > max {
+        format!("{}…", content.chars().take(max).collect::<String>())
+    } else {
+        content.to_string()
+    }
+}
+
+/// Formats reaction counts, e.g. `👍 2  🎉 1`
+fn format_reactions(reactions: &[ReactionCount]) -> String {
+    reactions
+        .iter()
+        .map(|reaction| format!("{} {}", reaction.emoji, reaction.count))
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+/// Describes a presence with its status message, e.g. `away: lunch`
+fn describe_presence(presence: Presence, status: Option<&str>) -> String {
+    let presence = match presence {
+        Presence::Online => "online",
+        Presence::Away => "away",
+        Presence::DoNotDisturb => "do not disturb",
+        Presence::Offline => "offline",
+    };
+
+    match status {
+        Some(status) => format!("{presence}: {status}"),
+        None => presence.to_string(),
+    }
+}
+
+/// Formats a number of bytes, e.g. `12.3 KiB`
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{size} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// Formats a chat message with its ID and the message it replies to
+fn format_chat_message(msg: &NewMessageRequest) -> String {
+    let mut line = String::new();
+
+    if let Some(quote) = &msg.reply_to {
+        line.push_str(&format!(
+            "  ↪ [#{}] {}: {}\n",
+            quote.id,
+            quote.user_name,
+            excerpt(&quote.content, 40)
+        ));
+    }
+
+    match msg.id {
+        Some(id) => line.push_str(&format!("[#{id}] {}: {}", msg.user_name, msg.content)),
+        None => line.push_str(&format!("{}: {}", msg.user_name, msg.content)),
+    }
+
+    if !msg.reactions.is_empty() {
+        line.push_str(&format!("  ({})", format_reactions(&msg.reactions)));
+    }
+
+    if let Some(attachment) = &msg.attachment {
+        line.push_str(&format!(
+            "\n  📎 {} ({}) /download {} <path>",
+            attachment.file_name,
+            format_size(attachment.size),
+            attachment.id
+        ));
+    }
+
+    line
+}
+
+/// Number of messages per page of `/history`
+const HISTORY_PAGE_LEN: usize = 20;
+
+/// Parses a day in local time, e.g. `2024-05-01`, to the moment it starts
+fn parse_day(day: &str) -> Result<DateTime<Utc>> {
+    NaiveDate::parse_from_str(day, "%Y-%m-%d")
+        .into_diagnostic()?
+        .and_hms_opt(0, 0, 0)
+        .and_then(|start| start.and_local_timezone(Local).earliest())
+        .map(|start| start.with_timezone(&Utc))
+        .ok_or(miette!("{day} is not a valid day"))
+}
+
+/// Parses the arguments of the search command, the room is left empty for the current one
+fn parse_search(arguments: Vec<String>) -> Result<SearchRequest> {
+    let mut request = SearchRequest::new(String::new(), String::new());
+    let mut words = Vec::new();
+
+    for argument in arguments.into_iter().filter(|term| !term.is_empty()) {
+        match argument.split_once(':') {
+            Some(("from", author)) => request.author = Some(author.to_string()),
+            Some(("since", day)) => request.since = Some(parse_day(day)?),
+            // The whole day is included
+            Some(("until", day)) => {
+                request.until = parse_day(day)?.checked_add_days(Days::new(1));
+            }
+            Some(("in", room)) => request.room = room.to_string(),
+            _ => words.push(argument),
+        }
+    }
+
+    let filtered = request.author.is_some() || request.since.is_some() || request.until.is_some();
+    if words.is_empty() && !filtered {
+        return Err(miette!("search not enough args"));
+    }
+    request.query = words.join(" ");
+
+    Ok(request)
+}
+
+/// Parses the arguments of the filter command
+fn parse_filter(arguments: Vec<String>) -> Result<Option<FilterSettings>> {
+    let mut arguments = arguments.into_iter();
+
+    let mode = match arguments.next().as_deref() {
+        Some("off") => return Ok(None),
+        Some("reject") => FilterMode::Reject,
+        Some("mask") => FilterMode::Mask,
+        Some("hold") => FilterMode::Hold,
+        _ => return Err(miette!("filter expects a mode")),
+    };
+
+    let (patterns, words): (Vec<String>, Vec<String>) = arguments
+        .filter(|term| !term.is_empty())
+        .partition(|term| term.len() > 2 && term.starts_with('/') && term.ends_with('/'));
+    let patterns: Vec<String> = patterns
+        .into_iter()
+        .map(|pattern| pattern[1..pattern.len() - 1].to_string())
+        .collect();
+
+    if words.is_empty() && patterns.is_empty() {
+        Err(miette!("filter not enough args"))
+    } else {
+        Ok(Some(FilterSettings::new(mode, words, patterns)))
+    }
+}
+
+/// Something the user asked for, a request to the server or something the client does itself
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// Sent to the server as it is
+    Request(ClientMessage),
+    /// Uploads a file and attaches it to a message in the current chat room
+    Upload { path: PathBuf },
+    /// Saves an attachment to a file
+    Download { id: AttachmentId, path: PathBuf },
+    /// Fetches the next page of the last search
+    MoreResults,
+    /// Fetches the latest messages of a chat room, the current one if none is given
+    History { room: Option<String>, limit: usize },
+    /// Fetches the page of messages before the last history page
+    OlderHistory,
+    /// Marks a chat room as read, the current one if none is given
+    MarkRead { room: Option<String> },
+    /// Lists the members of a chat room, the current one if none is given
+    Who { room: Option<String> },
+    /// Shows the fingerprint of our own key
+    OwnFingerprint,
+}
 
 impl Command {
     /// Instatiates a command from a parsed line
@@ -52,25 +292,197 @@ impl Command {
         let mut arguments = arguments.into_iter();
         let keyword = arguments.next().ok_or(miette!("expected args"))?;
 
-        match &keyword[..] {
-            "make" => Ok(Command::MakeChatRoom(ClientMakeChatRoomRequest {
+        let request = match &keyword[..] {
+            "make" => Ok(ClientMessage::MakeChatRoom(ClientMakeChatRoomRequest {
                 name: arguments
                     .next()
                     .ok_or(miette!("make chat room not enough args"))?,
             })),
-            "join" => Ok(Command::JoinChatRoom(JoinChatRoomRequest {
+            "join" => Ok(ClientMessage::JoinChatRoom(JoinChatRoomRequest {
                 name: arguments
                     .next()
                     .ok_or(miette!("make join chat room not enough args"))?,
             })),
-            "list" => Ok(Command::ListChatRooms()),
-            "cname" => Ok(Command::ChangeName(ChangeNameRequest {
+            "leave" => Ok(ClientMessage::LeaveChatRoom()),
+            "list" => Ok(ClientMessage::ListChatRooms()),
+            "cname" => Ok(ClientMessage::ChangeName(ChangeNameRequest {
                 new_name: arguments.next().ok_or(miette!("cname not enough args"))?,
             })),
-            "help" => Ok(Command::Help()),
-            "exit" => process::exit(0),
+            "admin" => Ok(ClientMessage::AdminLogin(AdminLoginRequest {
+                password: arguments.next().ok_or(miette!("admin not enough args"))?,
+            })),
+            "announce" => {
+                let content = arguments.collect::<Vec<String>>().join(" ");
+
+                if content.is_empty() {
+                    Err(miette!("announce not enough args"))
+                } else {
+                    Ok(ClientMessage::Announce(AnnounceRequest { content }))
+                }
+            }
+            "filter" => Ok(ClientMessage::SetFilter(SetFilterRequest::new(
+                parse_filter(arguments.collect())?,
+            ))),
+            "held" => Ok(ClientMessage::ListHeld()),
+            "approve" | "discard" => {
+                let id = arguments
+                    .next()
+                    .ok_or(miette!("{keyword} not enough args"))?
+                    .parse()
+                    .into_diagnostic()?;
+
+                Ok(ClientMessage::ReviewHeld(ReviewHeldRequest::new(
+                    id,
+                    keyword == "approve",
+                )))
+            }
+            "reply" => {
+                let id = arguments
+                    .next()
+                    .ok_or(miette!("reply not enough args"))?
+                    .parse()
+                    .into_diagnostic()?;
+                let content = arguments.collect::<Vec<String>>().join(" ");
+
+                if content.is_empty() {
+                    Err(miette!("reply not enough args"))
+                } else {
+                    Ok(ClientMessage::SendMessage(SendMessageRequest::reply(
+                        id, content,
+                    )))
+                }
+            }
+            "thread" => Ok(ClientMessage::FetchThread(FetchThreadRequest::new(
+                arguments
+                    .next()
+                    .ok_or(miette!("thread not enough args"))?
+                    .parse()
+                    .into_diagnostic()?,
+            ))),
+            "react" | "unreact" => {
+                let message_id = arguments
+                    .next()
+                    .ok_or(miette!("{keyword} not enough args"))?
+                    .parse()
+                    .into_diagnostic()?;
+                let emoji = arguments
+                    .next()
+                    .ok_or(miette!("{keyword} not enough args"))?;
+                let request = ReactRequest::new(message_id, emoji);
+
+                if keyword == "react" {
+                    Ok(ClientMessage::React(request))
+                } else {
+                    Ok(ClientMessage::Unreact(request))
+                }
+            }
+            "register" | "login" => {
+                let name = arguments
+                    .next()
+                    .ok_or(miette!("{keyword} not enough args"))?;
+                let password = arguments
+                    .next()
+                    .ok_or(miette!("{keyword} not enough args"))?;
+                let request = CredentialsRequest::new(name, password);
+
+                if keyword == "register" {
+                    Ok(ClientMessage::Register(request))
+                } else {
+                    Ok(ClientMessage::Login(request))
+                }
+            }
+            "msg" => {
+                let to = arguments.next().ok_or(miette!("msg not enough args"))?;
+                let content = arguments.collect::<Vec<String>>().join(" ");
+
+                if content.is_empty() {
+                    Err(miette!("msg not enough args"))
+                } else {
+                    Ok(ClientMessage::DirectMessage(DirectMessageRequest::new(
+                        to, content,
+                    )))
+                }
+            }
+            "read" => {
+                return Ok(Command::MarkRead {
+                    room: arguments.next(),
+                })
+            }
+            "search" => Ok(ClientMessage::Search(parse_search(arguments.collect())?)),
+            "more" => return Ok(Command::MoreResults),
+            "history" => {
+                return Ok(Command::History {
+                    room: arguments.next(),
+                    limit: HISTORY_PAGE_LEN,
+                })
+            }
+            "older" => return Ok(Command::OlderHistory),
+            // The client reads the file and sends the requests for it
+            "upload" => {
+                let path = arguments.next().ok_or(miette!("upload not enough args"))?;
+
+                return Ok(Command::Upload {
+                    path: PathBuf::from(path),
+                });
+            }
+            "download" => {
+                let id = arguments
+                    .next()
+                    .ok_or(miette!("download not enough args"))?;
+                let path = arguments
+                    .next()
+                    .ok_or(miette!("download not enough args"))?;
+
+                return Ok(Command::Download {
+                    id,
+                    path: PathBuf::from(path),
+                });
+            }
+            "away" | "dnd" => {
+                let status = arguments.collect::<Vec<String>>().join(" ");
+                let status = Some(status).filter(|status| !status.trim().is_empty());
+                let presence = if keyword == "away" {
+                    Presence::Away
+                } else {
+                    Presence::DoNotDisturb
+                };
+
+                Ok(ClientMessage::SetPresence(SetPresenceRequest::new(
+                    presence, status,
+                )))
+            }
+            "back" => Ok(ClientMessage::SetPresence(SetPresenceRequest::new(
+                Presence::Online,
+                None,
+            ))),
+            "who" => {
+                return Ok(Command::Who {
+                    room: arguments.next(),
+                })
+            }
+            "ignore" | "unignore" => {
+                let user = arguments
+                    .next()
+                    .ok_or(miette!("{keyword} not enough args"))?;
+                let request = IgnoreRequest::new(user);
+
+                if keyword == "ignore" {
+                    Ok(ClientMessage::Ignore(request))
+                } else {
+                    Ok(ClientMessage::Unignore(request))
+                }
+            }
+            "ignored" => Ok(ClientMessage::ListIgnored()),
+            "fingerprint" => match arguments.next() {
+                Some(user) => Ok(ClientMessage::FetchKey(FetchKeyRequest::new(user))),
+                None => return Ok(Command::OwnFingerprint),
+            },
+            "help" => Ok(ClientMessage::Help()),
+            "exit" => exit(),
             _ => Err(miette!("Not a valid argument")),
-        }
+        };
+
+        request.map(Command::Request)
     }
 
     /// Insantiates a command from an input line
@@ -86,8 +498,8 @@ impl Command {
 
             Command::from_arguments(args)
         } else {
-            Ok(Command::SendMessage(SendMessageRequest::new(
-                line.trim().to_string(),
+            Ok(Command::Request(ClientMessage::SendMessage(
+                SendMessageRequest::new(line.trim().to_string()),
             )))
         }
     }
@@ -97,6 +509,24 @@ impl Command {
 pub struct Frontend {
     reader: BufReader<Stdin>,
     pub current_chatroom: String,
+    /// Messages mentioning the user since the user last sent something
+    pub unread_mentions: usize,
+    /// Presence the user set
+    pub presence: Presence,
+    /// Keys read from a terminal in raw mode, `None` if whole lines are read instead
+    keys: Option<EventStream>,
+    /// Line the user is typing
+    input: String,
+    /// When the server was last told that the user is typing the current line
+    typing_sent: Option<Instant>,
+    /// Users in the chat room that are typing, until when they are shown
+    typing: BTreeMap<String, Instant>,
+}
+
+impl Drop for Frontend {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
 }
 
 impl Frontend {
@@ -106,33 +536,99 @@ impl Frontend {
         let frontend = Frontend {
             reader,
             current_chatroom: String::from("None"),
+            unread_mentions: 0,
+            presence: Presence::Online,
+            keys: read_single_keys().then(EventStream::new),
+            input: String::new(),
+            typing_sent: None,
+            typing: BTreeMap::new(),
         };
         frontend.print_prompt()?;
         Ok(frontend)
     }
 
+    /// Status shown above the prompt
+    fn status(&self) -> String {
+        let mut status = format!("room: {}", self.current_chatroom);
+
+        if self.presence != Presence::Online {
+            status.push_str(&format!(" | {}", describe_presence(self.presence, None)));
+        }
+
+        match self.unread_mentions {
+            0 => {}
+            1 => status.push_str(" | 1 unread mention"),
+            n => status.push_str(&format!(" | {n} unread mentions")),
+        }
+
+        let typing: Vec<&str> = self.typing.keys().map(String::as_str).collect();
+        match typing[..] {
+            [] => {}
+            [name] => status.push_str(&format!(" | {name} is typing…")),
+            [first, second] => status.push_str(&format!(" | {first} and {second} are typing…")),
+            _ => status.push_str(" | several people are typing…"),
+        }
+
+        status
+    }
+
+    /// Status and prompt below the output, with the input typed so far
+    fn prompt(&self) -> String {
+        format!("({})\n⤷ {}", self.status(), self.input)
+    }
+
+    /// Shows that another user in the chat room is typing, for a few seconds
+    pub fn show_typing(&mut self, user_name: String) -> Result<()> {
+        self.typing
+            .insert(user_name, Instant::now() + TYPING_TIMEOUT);
+        self.print_prompt()
+    }
+
+    /// Stops showing a user as typing, once their message arrived
+    pub fn stop_typing(&mut self, user_name: &str) {
+        self.typing.remove(user_name);
+    }
+
+    /// Stops showing anyone as typing, when the chat room changes
+    pub fn clear_typing(&mut self) {
+        self.typing.clear();
+    }
+
+    /// Stops showing users as typing whose last typing event is too old
+    pub fn expire_typing(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let typing = self.typing.len();
+        self.typing.retain(|_, until| *until > now);
+
+        if self.typing.len() < typing {
+            self.print_prompt()?;
+        }
+
+        Ok(())
+    }
+
     /// Prints any errors
     pub fn print_err(&self, error: &str) -> Result<()> {
         clear_lines(2)?;
 
-        print!(
-            "!err: {error}\n------------------------\n(room: {})\n⤷ ",
-            self.current_chatroom
-        );
+        out!("!err: {error}\n------------------------\n{}", self.prompt());
 
         flush_io();
 
         Ok(())
     }
 
+    /// Prints that the server closed the connection
+    pub fn print_disconnected(&self) {
+        outln!("\nDisconnected from server");
+        flush_io();
+    }
+
     /// Prints the prompt in the terminal interface
     pub fn print_prompt(&self) -> Result<()> {
         clear_lines(2)?;
 
-        print!(
-            "------------------------\n(room: {})\n⤷ ",
-            self.current_chatroom
-        );
+        out!("------------------------\n{}", self.prompt());
 
         flush_io();
 
@@ -143,9 +639,180 @@ impl Frontend {
     pub fn print_message(&self, msg: String, usr: String) -> Result<()> {
         clear_lines(2)?;
 
-        print!(
-            "{usr}: {msg}\n-------------------------\n(room: {})\n⤷ ",
-            self.current_chatroom
+        out!("{usr}: {msg}\n-------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints a message from a chat room in the terminal interface
+    pub fn print_chat_message(&self, msg: &NewMessageRequest) -> Result<()> {
+        clear_lines(2)?;
+
+        // Mentions of the user are highlighted and ring the terminal bell
+        let line = if msg.mentioned {
+            format!("\x07{}", format_chat_message(msg).yellow().bold())
+        } else {
+            format_chat_message(msg)
+        };
+        out!("{line}\n-------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints a direct message in the terminal interface, with the time it was sent if it was
+    /// queued while the user was offline
+    pub fn print_direct_message(&self, msg: &DirectMessageResponse, verified: bool) -> Result<()> {
+        clear_lines(2)?;
+
+        // Messages encrypted with the published key of their sender are marked with a lock
+        let lock = if verified { "🔒 " } else { "" };
+        let line = if msg.queued {
+            let sent_at = msg.sent_at.with_timezone(&chrono::Local);
+            format!(
+                "[{}] {lock}{} → you: {}",
+                sent_at.format("%Y-%m-%d %H:%M"),
+                msg.from,
+                msg.content
+            )
+        } else {
+            format!("{lock}{} → you: {}", msg.from, msg.content)
+        };
+        out!(
+            "{}\n-------------------------\n{}",
+            line.magenta(),
+            self.prompt()
+        );
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints the reactions to a message after they changed in the terminal interface
+    pub fn print_reactions(
+        &self,
+        message_id: MessageId,
+        reactions: &[ReactionCount],
+    ) -> Result<()> {
+        clear_lines(2)?;
+
+        let reactions = if reactions.is_empty() {
+            "no reactions".to_string()
+        } else {
+            format_reactions(reactions)
+        };
+        out!(
+            "{}\n-------------------------\n{}",
+            format!("[#{message_id}] {reactions}").dark_grey(),
+            self.prompt()
+        );
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints a whole thread in the terminal interface
+    pub fn print_thread(&self, room: &str, messages: &[NewMessageRequest]) -> Result<()> {
+        clear_lines(2)?;
+
+        outln!("Thread in {room}:");
+        for msg in messages {
+            // Replies are indented below the first message of the thread
+            let indent = if msg.thread.is_some() { "\t\t" } else { "\t" };
+            for line in format_chat_message(msg).lines() {
+                outln!("{indent}{line}");
+            }
+        }
+        out!("------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints a page of the history of a room in the terminal interface
+    pub fn print_history(&self, history: &HistoryResponse) -> Result<()> {
+        clear_lines(2)?;
+
+        outln!("History of {}:", history.room);
+        if history.next_before_id.is_some() {
+            outln!("\t{}", "/older for older messages".dark_grey());
+        }
+        if history.messages.is_empty() {
+            outln!("\tno messages");
+        }
+        for msg in &history.messages {
+            let sent_at = msg.sent_at.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+            for line in format_chat_message(msg).lines() {
+                outln!("\t{} {line}", sent_at.to_string().dark_grey());
+            }
+        }
+        out!("------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints a page of search results in the terminal interface
+    pub fn print_search_results(&self, results: &SearchResultsResponse) -> Result<()> {
+        clear_lines(2)?;
+
+        outln!("Results for \"{}\" in {}:", results.query, results.room);
+        if results.messages.is_empty() {
+            outln!("\tno messages found");
+        }
+        for msg in &results.messages {
+            let sent_at = msg.sent_at.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+            let id = msg.id.map(|id| format!("[#{id}] ")).unwrap_or_default();
+            outln!(
+                "\t{id}{} {}: {}",
+                sent_at.to_string().dark_grey(),
+                msg.user_name,
+                msg.content
+            );
+        }
+        if results.next_before_id.is_some() {
+            outln!("\t{}", "/more for older results".dark_grey());
+        }
+        out!("------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints a server-wide announcement in the terminal interface
+    pub fn print_announcement(&self, content: &str) -> Result<()> {
+        clear_lines(2)?;
+
+        let banner = "=========================".yellow().bold();
+        out!(
+            "{banner}\n{} {}\n{banner}\n-------------------------\n{}",
+            "ANNOUNCEMENT:".yellow().bold(),
+            content.bold(),
+            self.prompt()
+        );
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints the message of the day in the terminal interface
+    pub fn print_motd(&self, content: &str) -> Result<()> {
+        clear_lines(2)?;
+
+        out!(
+            "{}\n{}\n-------------------------\n{}",
+            "Message of the day:".cyan().bold(),
+            content.cyan(),
+            self.prompt()
         );
 
         flush_io();
@@ -157,10 +824,10 @@ impl Frontend {
     pub fn print_command(&self, msg: String) -> Result<()> {
         clear_lines(3)?;
 
-        print!(
-            "-> {}\n-------------------------\n(room: {})\n⤷ ",
+        out!(
+            "-> {}\n-------------------------\n{}",
             crop_letters(&msg, 1),
-            self.current_chatroom
+            self.prompt()
         );
 
         flush_io();
@@ -172,10 +839,7 @@ impl Frontend {
     pub fn print_input(&self, inp: String) -> Result<()> {
         clear_lines(3)?;
 
-        print!(
-            "You: {inp}\n-------------------------\n(room: {})\n⤷ ",
-            self.current_chatroom
-        );
+        out!("You: {inp}\n-------------------------\n{}", self.prompt());
 
         flush_io();
 
@@ -187,10 +851,7 @@ impl Frontend {
         clear_lines(2)?;
 
         print_help();
-        print!(
-            "------------------------\n(room: {})\n⤷ ",
-            self.current_chatroom
-        );
+        out!("------------------------\n{}", self.prompt());
 
         flush_io();
 
@@ -201,43 +862,217 @@ impl Frontend {
     pub fn print_invalid_command_help(&self, command: String) -> Result<()> {
         clear_lines(2)?;
 
-        print!("!invalid command: {}", command);
+        out!("!invalid command: {}", command);
         print_help();
-        print!(
-            "------------------------\n(room: {})\n⤷ ",
-            self.current_chatroom
-        );
+        out!("------------------------\n{}", self.prompt());
 
         flush_io();
 
         Ok(())
     }
 
-    /// Prints list of rooms in the terminal interface
-    pub fn print_rooms(&self, rooms: Vec<String>) -> Result<()> {
+    /// Prints list of rooms in the terminal interface, with unread counts if there are any
+    pub fn print_rooms(&self, rooms: Vec<String>, unread: &BTreeMap<String, usize>) -> Result<()> {
         clear_lines(2)?;
 
-        println!("Chat rooms:");
-        print!("\t{}", rooms.join("\n\t"));
-        print!(
-            "\n------------------------\n(room: {})\n⤷ ",
-            self.current_chatroom
-        );
+        let rooms: Vec<String> = rooms
+            .into_iter()
+            .map(|room| match unread.get(&room) {
+                Some(&count) if count > 0 => format!("{room} ({count} unread)"),
+                _ => room,
+            })
+            .collect();
+
+        outln!("Chat rooms:");
+        out!("\t{}", rooms.join("\n\t"));
+        out!("\n------------------------\n{}", self.prompt());
 
         flush_io();
 
         Ok(())
     }
 
-    /// Returns a next command if there is one
-    pub async fn next(&mut self) -> Result<Option<Command>> {
+    /// Prints a change of presence of another user in the terminal interface
+    pub fn print_presence(&self, update: &PresenceResponse) -> Result<()> {
+        let line = match update.presence {
+            Presence::Online => format!("{} is back", update.user_name),
+            presence => format!(
+                "{} is now {}",
+                update.user_name,
+                describe_presence(presence, update.status.as_deref())
+            ),
+        };
+
+        self.print_message(line, "ChatRoom".to_string())
+    }
+
+    /// Prints the members of a room with their presence in the terminal interface
+    pub fn print_who(&self, who: &WhoResponse) -> Result<()> {
+        clear_lines(2)?;
+
+        outln!("Members of {}:", who.room);
+        for user in &who.users {
+            outln!(
+                "\t{} ({})",
+                user.user_name,
+                describe_presence(user.presence, user.status.as_deref())
+            );
+        }
+        out!("------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints the key fingerprint of a user in the terminal interface, with a warning if the
+    /// key changed since it was last seen
+    pub fn print_fingerprint(&self, user: &str, fingerprint: &str, changed: bool) -> Result<()> {
+        if changed {
+            let warning = format!(
+                "The key of {user} changed, verify its new fingerprint with them: {fingerprint}"
+            );
+            return self.print_err(&warning.yellow().bold().to_string());
+        }
+
+        self.print_message(
+            format!("Key fingerprint of {user}: {fingerprint}"),
+            "Server".to_string(),
+        )
+    }
+
+    /// Prints the ignored users in the terminal interface
+    pub fn print_ignored(&self, users: &[String]) -> Result<()> {
+        clear_lines(2)?;
+
+        if users.is_empty() {
+            outln!("You ignore nobody");
+        } else {
+            outln!("Ignored users:");
+            outln!("\t{}", users.join("\n\t"));
+        }
+        out!("------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Prints the messages held by the filter of a room in the terminal interface
+    pub fn print_held(&self, room: &str, messages: Vec<HeldMessage>) -> Result<()> {
+        clear_lines(2)?;
+
+        outln!("Held messages in {room}:");
+        for message in messages {
+            outln!(
+                "\t[{}] {}: {}",
+                message.id,
+                message.user_name,
+                message.content
+            );
+        }
+        out!("------------------------\n{}", self.prompt());
+
+        flush_io();
+
+        Ok(())
+    }
+
+    /// Reads a line from input that is not a terminal
+    async fn read_line(&mut self) -> Result<String> {
         let mut buffer = Vec::new();
-        self.reader
+        let read = self
+            .reader
             .read_until(b'\n', &mut buffer)
             .await
             .into_diagnostic()?;
 
-        let line = String::from_utf8(buffer).into_diagnostic()?;
+        // End of input, same as /exit
+        if read == 0 {
+            exit();
+        }
+
+        String::from_utf8(buffer).into_diagnostic()
+    }
+
+    /// Whether the server should be told that the user is typing, which is not the case for
+    /// commands or if it was told a moment ago
+    fn announce_typing(&mut self) -> bool {
+        let recently = self
+            .typing_sent
+            .is_some_and(|sent| sent.elapsed() < TYPING_INTERVAL);
+        if self.input.starts_with('/') || recently {
+            return false;
+        }
+
+        self.typing_sent = Some(Instant::now());
+        true
+    }
+
+    /// Reads keys from a terminal and echoes them until a line is complete, returns `None`
+    /// in between if the server should be told that the user is typing
+    async fn read_keys(&mut self) -> Result<Option<String>> {
+        loop {
+            let Some(keys) = self.keys.as_mut() else {
+                return Err(miette!("Terminal is not read by single keys"));
+            };
+            let key = match keys.next().await {
+                Some(event) => match event.into_diagnostic()? {
+                    Event::Key(key) if key.kind != KeyEventKind::Release => key,
+                    _ => continue,
+                },
+                // End of input, same as /exit
+                None => exit(),
+            };
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+
+            match key.code {
+                KeyCode::Enter => {
+                    outln!();
+                    self.typing_sent = None;
+                    return Ok(Some(mem::take(&mut self.input)));
+                }
+                // Ctrl-C, and Ctrl-D on an empty line like the end of input
+                KeyCode::Char('c') if control => exit(),
+                KeyCode::Char('d') if control && self.input.is_empty() => exit(),
+                KeyCode::Backspace if self.input.pop().is_some() => {
+                    out!("\x08 \x08");
+                    flush_io();
+                }
+                KeyCode::Char(c) if !control && !c.is_control() => {
+                    self.input.push(c);
+                    out!("{c}");
+                    flush_io();
+
+                    if self.announce_typing() {
+                        return Ok(None);
+                    }
+                }
+                // Cursor and function keys are not supported
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns a next command if there is one
+    pub async fn next(&mut self) -> Result<Option<Command>> {
+        let line = if self.keys.is_some() {
+            match self.read_keys().await? {
+                Some(line) => line,
+                None => return Ok(Some(Command::Request(ClientMessage::Typing()))),
+            }
+        } else {
+            self.read_line().await?
+        };
+
+        // The server refuses empty messages, so there is no point in sending them
+        if line.trim().is_empty() {
+            self.print_prompt()?;
+            return Ok(None);
+        }
+
+        // Typing something means the user caught up on the mentions
+        self.unread_mentions = 0;
 
         if is_command(line.clone()) {
             self.print_command(line.trim().to_string())?;
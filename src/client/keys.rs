@@ -0,0 +1,216 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key,
+};
+use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::server::communication::{
+    client::{PublicKeyBytes, SealedContent},
+    to_hex,
+};
+
+/// Separates the keys derived for direct messages from any other use of the shared secret
+const KEY_CONTEXT: &[u8] = b"chat-server direct message v1";
+
+/// Short, readable hash of a public key that users compare to verify each other
+pub fn fingerprint(key: &PublicKeyBytes) -> String {
+    let hash = to_hex(&Sha256::digest(key)[..16]);
+
+    hash.as_bytes()
+        .chunks(4)
+        .map(|group| String::from_utf8_lossy(group).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Decodes a public key written as hex
+fn key_from_hex(text: &str) -> Option<PublicKeyBytes> {
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+
+    let mut key = [0; 32];
+    for (byte, pair) in key.iter_mut().zip(text.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Public keys of other users, kept next to the key file so a changed key is noticed across
+/// restarts of the client
+pub struct KnownKeys {
+    /// File the keys are saved in, none if they only last as long as the client runs
+    path: Option<PathBuf>,
+    /// Last key seen of each user
+    keys: HashMap<String, PublicKeyBytes>,
+}
+
+impl KnownKeys {
+    /// Loads the keys saved next to the given key file, if there are any yet
+    pub fn load(key_file: Option<&Path>) -> Result<Self> {
+        let path = key_file.map(|key_file| {
+            let mut name = key_file.as_os_str().to_owned();
+            name.push(".known");
+            PathBuf::from(name)
+        });
+
+        let mut keys = HashMap::new();
+        if let Some(path) = path.as_deref().filter(|path| path.exists()) {
+            let text = fs::read_to_string(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not read known keys {}", path.display()))?;
+
+            // Names contain no control characters, so a tab separates them from the key
+            for line in text.lines() {
+                if let Some((user, key)) = line.split_once('\t') {
+                    if let Some(key) = key_from_hex(key) {
+                        keys.insert(user.to_string(), key);
+                    }
+                }
+            }
+        }
+
+        Ok(Self { path, keys })
+    }
+
+    /// Last key seen of a user
+    pub fn get(&self, user: &str) -> Option<&PublicKeyBytes> {
+        self.keys.get(user)
+    }
+
+    /// Remembers the key of a user, returns whether it replaced a different one
+    pub fn pin(&mut self, user: &str, key: PublicKeyBytes) -> Result<bool> {
+        let previous = self.keys.insert(user.to_string(), key);
+        if previous == Some(key) {
+            return Ok(false);
+        }
+
+        self.save()?;
+        Ok(previous.is_some())
+    }
+
+    /// Writes the keys to their file
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let text: String = self
+            .keys
+            .iter()
+            .map(|(user, key)| format!("{user}\t{}\n", to_hex(key)))
+            .collect();
+        fs::write(path, text)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not save known keys {}", path.display()))
+    }
+}
+
+/// Key pair of the client, encrypts direct messages so only their recipient can read them
+pub struct KeyPair {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl KeyPair {
+    /// Generates a key pair that is lost when the client exits
+    pub fn generate() -> Self {
+        let secret = StaticSecret::random_from_rng(OsRng);
+        let public = PublicKey::from(&secret);
+
+        Self { secret, public }
+    }
+
+    /// Loads the key pair from a file, creates it if it is missing so messages that were
+    /// queued while offline can still be read after a restart
+    pub fn load_or_create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            let bytes: [u8; 32] = fs::read(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not read key {}", path.display()))?
+                .try_into()
+                .map_err(|_| miette!("Key {} is not 32 bytes long", path.display()))?;
+            let secret = StaticSecret::from(bytes);
+            let public = PublicKey::from(&secret);
+
+            return Ok(Self { secret, public });
+        }
+
+        let keys = Self::generate();
+
+        // Only the user may read the secret key
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| file.write_all(keys.secret.as_bytes()))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not save key {}", path.display()))?;
+
+        Ok(keys)
+    }
+
+    /// Public key to publish to the server
+    pub fn public_key(&self) -> PublicKeyBytes {
+        self.public.to_bytes()
+    }
+
+    /// Derives the key shared with a peer, both sides arrive at the same one
+    fn shared_key(&self, peer: &PublicKeyBytes) -> Result<Key> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(*peer));
+        // Keys of low order would make the shared secret predictable
+        if !shared.was_contributory() {
+            return Err(miette!("Invalid public key"));
+        }
+
+        let own = self.public_key();
+        let (first, second) = if own <= *peer {
+            (&own, peer)
+        } else {
+            (peer, &own)
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_CONTEXT);
+        hasher.update(shared.as_bytes());
+        hasher.update(first);
+        hasher.update(second);
+
+        Ok(hasher.finalize())
+    }
+
+    /// Encrypts the content of a direct message for the owner of a public key
+    pub fn seal(&self, peer: &PublicKeyBytes, content: &str) -> Result<SealedContent> {
+        let cipher = ChaCha20Poly1305::new(&self.shared_key(peer)?);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, content.as_bytes())
+            .map_err(|_| miette!("Could not encrypt message"))?;
+
+        Ok(SealedContent::new(
+            self.public_key(),
+            nonce.into(),
+            ciphertext,
+        ))
+    }
+
+    /// Decrypts the content of a direct message sent to this key pair
+    pub fn open(&self, sealed: &SealedContent) -> Result<String> {
+        let cipher = ChaCha20Poly1305::new(&self.shared_key(&sealed.sender_key)?);
+        let content = cipher
+            .decrypt(&sealed.nonce.into(), sealed.ciphertext.as_slice())
+            .map_err(|_| miette!("Could not decrypt message"))?;
+
+        String::from_utf8(content).into_diagnostic()
+    }
+}
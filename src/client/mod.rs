@@ -1,4 +1,8 @@
+mod chat_client;
+#[allow(clippy::module_inception)]
 mod client;
 mod frontend;
+pub mod keys;
 
+pub use chat_client::{ChatClient, Events};
 pub use client::Client;
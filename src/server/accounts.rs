@@ -0,0 +1,270 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+};
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::server::communication::{
+    client::{MessageId, PublicKeyBytes},
+    error::{ErrorCode, ServerError},
+    server::DirectMessageResponse,
+};
+
+/// Direct messages kept for a user while they are offline, more are refused
+pub const MAX_QUEUED: usize = 100;
+
+/// Hashes a password for storage, slow on purpose so it should not run on the async runtime
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| miette!("Could not hash password: {err}"))
+}
+
+/// Checks a password against a stored hash, slow on purpose like [`hash_password`]
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// A registered identity
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Account {
+    /// Argon2 hash of the password
+    password_hash: String,
+    /// Direct messages that arrived while the user was offline
+    queued: Vec<DirectMessageResponse>,
+    /// Last read message per chat room
+    read_markers: HashMap<String, MessageId>,
+    /// Identities whose messages are not delivered
+    #[serde(default)]
+    ignored: BTreeSet<String>,
+    /// Key other users encrypt their direct messages with, published by the client
+    #[serde(default)]
+    public_key: Option<PublicKeyBytes>,
+}
+
+/// All registered identities, saved to a file if the server has a data directory
+#[derive(Debug, Default)]
+pub struct Accounts {
+    /// Accounts by user name
+    accounts: HashMap<String, Account>,
+    /// File the accounts are saved to
+    path: Option<PathBuf>,
+    /// Latest serialized accounts, written to the file by [`Accounts::writer`]
+    snapshots: Option<watch::Sender<String>>,
+}
+
+/// Writes a file by writing to a temporary file first, so a crash never leaves half a file
+/// behind
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, contents).into_diagnostic()?;
+    fs::rename(&tmp, path).into_diagnostic()
+}
+
+/// Writes the accounts whenever they change until the accounts are dropped, changes made while
+/// a write is running are written together afterwards
+async fn write(path: PathBuf, mut snapshots: watch::Receiver<String>) {
+    while snapshots.changed().await.is_ok() {
+        let contents = snapshots.borrow_and_update().clone();
+        let file = path.clone();
+
+        let result = tokio::task::spawn_blocking(move || write_file(&file, &contents))
+            .await
+            .into_diagnostic()
+            .and_then(|result| result);
+        if let Err(report) = result {
+            error!(error = %report, path = %path.display(), "Could not save accounts");
+        }
+    }
+}
+
+impl Accounts {
+    /// Loads the accounts from `accounts.json` in the data directory, starts empty if it is missing
+    pub fn load(data_dir: &Path) -> Result<Self> {
+        let path = data_dir.join("accounts.json");
+
+        let accounts = if path.exists() {
+            let contents = fs::read_to_string(&path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not read accounts {}", path.display()))?;
+
+            serde_json::from_str(&contents)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not parse accounts {}", path.display()))?
+        } else {
+            fs::create_dir_all(data_dir)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not create {}", data_dir.display()))?;
+            HashMap::new()
+        };
+
+        let (snapshots, _) = watch::channel(String::new());
+
+        Ok(Self {
+            accounts,
+            path: Some(path),
+            snapshots: Some(snapshots),
+        })
+    }
+
+    /// Task that saves the accounts to disk off the async runtime, none if they are only kept
+    /// in memory
+    pub fn writer(&self) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let path = self.path.clone()?;
+        let snapshots = self.snapshots.as_ref()?.subscribe();
+
+        Some(write(path, snapshots))
+    }
+
+    /// Hands the accounts to the writer, errors are logged as the change already happened in
+    /// memory
+    fn save(&self) {
+        let Some(snapshots) = &self.snapshots else {
+            return;
+        };
+
+        match serde_json::to_string_pretty(&self.accounts) {
+            Ok(contents) => {
+                snapshots.send_replace(contents);
+            }
+            Err(err) => error!(error = %err, "Could not serialize accounts"),
+        }
+    }
+
+    /// Checks whether a name belongs to a registered identity
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    /// Returns the password hash of an account
+    pub fn password_hash(&self, name: &str) -> Option<String> {
+        self.accounts
+            .get(name)
+            .map(|account| account.password_hash.clone())
+    }
+
+    /// Registers a new identity with an already hashed password
+    pub fn create(&mut self, name: String, password_hash: String) -> Result<(), ServerError> {
+        if self.is_registered(&name) {
+            return Err(ServerError::new(ErrorCode::NameTaken).details(name));
+        }
+
+        let account = Account {
+            password_hash,
+            ..Default::default()
+        };
+        self.accounts.insert(name, account);
+        self.save();
+
+        Ok(())
+    }
+
+    /// Queues a direct message for an offline user
+    pub fn queue(&mut self, name: &str, message: DirectMessageResponse) -> Result<(), ServerError> {
+        let Some(account) = self.accounts.get_mut(name) else {
+            return Err(ServerError::new(ErrorCode::UserNotFound).details(name));
+        };
+        if account.queued.len() >= MAX_QUEUED {
+            return Err(ServerError::new(ErrorCode::MailboxFull).details(name));
+        }
+
+        account.queued.push(message);
+        self.save();
+
+        Ok(())
+    }
+
+    /// Removes and returns all queued direct messages of a user
+    pub fn take_queued(&mut self, name: &str) -> Vec<DirectMessageResponse> {
+        let queued = self
+            .accounts
+            .get_mut(name)
+            .map(|account| std::mem::take(&mut account.queued))
+            .unwrap_or_default();
+
+        if !queued.is_empty() {
+            self.save();
+        }
+
+        queued
+    }
+
+    /// Last message a user read in a chat room
+    pub fn read_marker(&self, name: &str, room: &str) -> Option<MessageId> {
+        self.accounts
+            .get(name)
+            .and_then(|account| account.read_markers.get(room).copied())
+    }
+
+    /// Marks a chat room as read up to the given message
+    pub fn mark_read(&mut self, name: &str, room: &str, id: MessageId) {
+        if let Some(account) = self.accounts.get_mut(name) {
+            account.read_markers.insert(room.to_string(), id);
+            self.save();
+        }
+    }
+
+    /// Identities whose messages are not delivered to a user
+    pub fn ignored(&self, name: &str) -> BTreeSet<String> {
+        self.accounts
+            .get(name)
+            .map(|account| account.ignored.clone())
+            .unwrap_or_default()
+    }
+
+    /// Checks whether a user ignores another one
+    pub fn is_ignoring(&self, name: &str, user: &str) -> bool {
+        self.accounts
+            .get(name)
+            .is_some_and(|account| account.ignored.contains(user))
+    }
+
+    /// Starts or stops ignoring another identity
+    pub fn set_ignored(&mut self, name: &str, user: &str, ignore: bool) {
+        let Some(account) = self.accounts.get_mut(name) else {
+            return;
+        };
+
+        let changed = if ignore {
+            account.ignored.insert(user.to_string())
+        } else {
+            account.ignored.remove(user)
+        };
+        if changed {
+            self.save();
+        }
+    }
+
+    /// Public key a user published for direct messages
+    pub fn public_key(&self, name: &str) -> Option<PublicKeyBytes> {
+        self.accounts
+            .get(name)
+            .and_then(|account| account.public_key)
+    }
+
+    /// Replaces the public key of a user, e.g. when it logs in from another device
+    pub fn set_public_key(&mut self, name: &str, public_key: PublicKeyBytes) {
+        if let Some(account) = self.accounts.get_mut(name) {
+            if account.public_key != Some(public_key) {
+                account.public_key = Some(public_key);
+                self.save();
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use miette::{miette, IntoDiagnostic, Result};
+use std::{
+    fs::{self, Permissions},
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::{mpsc, oneshot, RwLock},
+};
+use tracing::{info, warn};
+
+use crate::server::{backend::Backend, config::ReloadReport};
+
+/// Request to reload the config, answered with the outcome
+pub type ReloadRequest = oneshot::Sender<Result<ReloadReport>>;
+
+/// Commands understood by the admin interface
+const HELP: &str = "commands:
+\tconnections\t\tlists all connected clients
+\trooms\t\t\tlists all chat rooms
+\tannounce <message>\tsends an announcement to all clients
+\tkick <uuid|name>\tdisconnects a client
+\tdelete-room <name>\tdeletes a chat room
+\tdump\t\t\tprints the server state as JSON
+\treload\t\t\treloads the config file";
+
+/// Executes a single admin command and returns the response
+async fn run_command(
+    line: &str,
+    backend: &RwLock<Backend>,
+    reload: &mpsc::Sender<ReloadRequest>,
+) -> Result<String> {
+    let (command, argument) = match line.split_once(' ') {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (line, ""),
+    };
+
+    let require_argument = || {
+        if argument.is_empty() {
+            Err(miette!("{command} requires an argument"))
+        } else {
+            Ok(argument)
+        }
+    };
+
+    match command {
+        "help" => Ok(HELP.to_string()),
+        "connections" => {
+            let connections = backend.read().await.connections();
+            let lines: Vec<String> = connections
+                .iter()
+                .map(|c| {
+                    let room = c.room.as_deref().unwrap_or("-");
+                    format!("{}\t{}\t{}\t{room}", c.uuid, c.addr, c.name)
+                })
+                .collect();
+
+            Ok(format!("{} connections\n{}", lines.len(), lines.join("\n")))
+        }
+        "rooms" => {
+            let rooms = backend.read().await.rooms();
+            let lines: Vec<String> = rooms
+                .iter()
+                .map(|r| format!("{}\t{} members", r.name, r.members))
+                .collect();
+
+            Ok(format!("{} rooms\n{}", lines.len(), lines.join("\n")))
+        }
+        "announce" => {
+            let message = require_argument()?;
+            let reached = backend.read().await.announce(message);
+            info!(reached, "Sent announcement");
+
+            Ok(format!("Announced to {reached} clients"))
+        }
+        "kick" => {
+            let who = require_argument()?;
+            let kicked = backend.read().await.kick(who);
+            info!(who, kicked, "Kicked clients");
+
+            match kicked {
+                0 => Err(miette!("No client matches {who}")),
+                n => Ok(format!("Disconnected {n} clients")),
+            }
+        }
+        "delete-room" => {
+            let name = require_argument()?;
+            backend.write().await.delete_room(name)?;
+            info!(room = name, "Deleted chat room");
+
+            Ok(format!("Deleted room {name}"))
+        }
+        "dump" => {
+            let snapshot = backend.read().await.snapshot();
+            serde_json::to_string_pretty(&snapshot).into_diagnostic()
+        }
+        "reload" => {
+            let (reply, outcome) = oneshot::channel();
+            reload
+                .send(reply)
+                .await
+                .map_err(|_| miette!("Server is not running"))?;
+            let report = outcome.await.into_diagnostic()??;
+
+            Ok(format!(
+                "applied: {:?}\nrequires restart: {:?}\nrequires reconnect: {:?}",
+                report.applied, report.requires_restart, report.requires_reconnect
+            ))
+        }
+        _ => Err(miette!("Unknown command {command}, try help")),
+    }
+}
+
+/// Handles a single operator session on the admin socket
+async fn handle_session(
+    stream: UnixStream,
+    backend: Arc<RwLock<Backend>>,
+    reload: mpsc::Sender<ReloadRequest>,
+) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Some(line) = lines.next_line().await.into_diagnostic()? {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let response = match run_command(line, &backend, &reload).await {
+            Ok(response) => response,
+            Err(report) => format!("error: {report}"),
+        };
+
+        write
+            .write_all(format!("{response}\n").as_bytes())
+            .await
+            .into_diagnostic()?;
+    }
+
+    Ok(())
+}
+
+/// Serves the admin interface on a unix socket at the given path
+pub async fn serve(
+    path: PathBuf,
+    backend: Arc<RwLock<Backend>>,
+    reload: mpsc::Sender<ReloadRequest>,
+) -> Result<()> {
+    // Remove a socket left behind by a previous run
+    let _ = fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path).into_diagnostic()?;
+    fs::set_permissions(&path, Permissions::from_mode(0o600)).into_diagnostic()?;
+    info!(path = %path.display(), "Serving admin interface");
+
+    loop {
+        let (stream, _) = listener.accept().await.into_diagnostic()?;
+        let backend = backend.clone();
+        let reload = reload.clone();
+
+        tokio::spawn(async move {
+            if let Err(report) = handle_session(stream, backend, reload).await {
+                warn!(error = %report, "Admin session failed");
+            }
+        });
+    }
+}
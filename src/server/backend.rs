@@ -1,75 +1,1050 @@
-use miette::{miette, Result};
-use std::collections::HashMap;
-use tokio::sync::broadcast::{self, Sender};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+};
+use tokio::sync::{
+    broadcast::{self, Sender},
+    mpsc,
+};
+use uuid::Uuid;
 
-use crate::server::communication::ChatMessage;
+use crate::server::{
+    accounts::Accounts,
+    blobs::BlobStore,
+    cluster::{Cluster, MessageKey, PeerMessage},
+    communication::{
+        client::{FilterMode, MessageId, Presence, SearchRequest},
+        error::{ErrorCode, ServerError},
+        server::{
+            DirectMessageResponse, HeldMessage, NewMessageRequest, PresenceResponse, Quote,
+            ReactionCount, ReactionsResponse,
+        },
+        Broadcast, ChatMessage,
+    },
+    filter::ContentFilter,
+    search::SearchIndex,
+};
+
+/// Connections that reacted to a message, by emoji
+type Reactions = BTreeMap<String, BTreeSet<Uuid>>;
+
+/// Maximum number of messages a chatroom holds back for review at the same time
+const MAX_HELD: usize = 100;
+
+/// Maximum number of different emoji a message can be reacted with
+const MAX_REACTIONS: usize = 20;
+
+/// Who may moderate a chatroom
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Owner {
+    /// A client that was not logged in, for as long as it stays connected
+    Connection(Uuid),
+    /// A registered identity, whoever logs in to it
+    Identity(String),
+}
 
 /// Contains a chatroom broadcast channel
 pub struct ChatRoom {
+    /// Name of the chatroom
+    name: String,
     /// Chatroom broadcast channel
-    send: Sender<ChatMessage>,
+    send: Sender<Broadcast>,
+    /// Client that created the chatroom, rooms from the config have no owner
+    owner: Option<Owner>,
+    /// Filter applied to messages before they are broadcast
+    filter: Option<ContentFilter>,
+    /// Messages kept back by the filter until a moderator reviews them
+    held: BTreeMap<u64, ChatMessage>,
+    /// ID of the next held message
+    next_held_id: u64,
+    /// Most recent messages posted in the chatroom, oldest first
+    history: VecDeque<ChatMessage>,
+    /// Maximum number of messages kept in the history
+    history_len: usize,
+    /// ID of the next posted message
+    next_message_id: MessageId,
+    /// Reactions to the messages in the history
+    reactions: HashMap<MessageId, Reactions>,
+    /// Keys of the messages in the history that were posted on other instances of the cluster
+    keys: HashMap<MessageId, MessageKey>,
+    /// IDs of the messages in the history that were posted on other instances, by their key
+    relayed_ids: HashMap<MessageKey, MessageId>,
+    /// Words of the messages in the history
+    index: SearchIndex,
+    /// Relays what is broadcast in the chatroom to the other instances of the cluster
+    cluster: Cluster,
 }
 
 impl ChatRoom {
-    /// Creates a new chatroom
-    pub fn new() -> Self {
-        let (send, _) = broadcast::channel(10);
-        Self { send }
+    /// Creates a new chatroom with a broadcast channel of the given capacity
+    pub fn new(
+        name: String,
+        capacity: usize,
+        history_len: usize,
+        owner: Option<Owner>,
+        cluster: Cluster,
+    ) -> Self {
+        let (send, _) = broadcast::channel(capacity);
+        Self {
+            name,
+            send,
+            owner,
+            filter: None,
+            held: BTreeMap::new(),
+            next_held_id: 0,
+            history: VecDeque::new(),
+            history_len,
+            next_message_id: 0,
+            reactions: HashMap::new(),
+            keys: HashMap::new(),
+            relayed_ids: HashMap::new(),
+            index: SearchIndex::default(),
+            cluster,
+        }
+    }
+
+    /// Sends to all members of the chatroom, on this instance and the others of the cluster
+    fn broadcast(&self, broadcast: Broadcast) {
+        self.cluster.relay_broadcast(&self.name, &broadcast);
+        let _ = self.send.send(broadcast);
     }
 
     /// Subscribe to a chatroom
-    pub fn subscribe(&self, name: &str) -> broadcast::Receiver<ChatMessage> {
-        let _ = self.send.send(ChatMessage {
-            sender_uuid: "".to_string(),
-            sender_name: "ChatRoom".to_string(),
-            content: format!("User {name} joined the room"),
-        });
+    pub fn subscribe(&self, name: &str) -> broadcast::Receiver<Broadcast> {
+        self.broadcast(Broadcast::Message(ChatMessage::new(
+            "".to_string(),
+            "ChatRoom".to_string(),
+            format!("User {name} joined the room"),
+        )));
         self.send.subscribe()
     }
 
+    /// Looks up a message in the history
+    fn find(&self, id: MessageId) -> Result<&ChatMessage, ServerError> {
+        // IDs are increasing, so the history is sorted by them
+        self.history
+            .binary_search_by_key(&Some(id), |message| message.id)
+            .map(|index| &self.history[index])
+            .map_err(|_| ServerError::new(ErrorCode::MessageNotFound).details(id.to_string()))
+    }
+
+    /// Key of a message in the history, identifies it on all instances of the cluster
+    fn key_of(&self, id: MessageId) -> MessageKey {
+        self.keys.get(&id).copied().unwrap_or(MessageKey {
+            node: self.cluster.node(),
+            id,
+        })
+    }
+
+    /// ID of the message with the given key, if it is in the history
+    fn id_of(&self, key: MessageKey) -> Option<MessageId> {
+        if key.node == self.cluster.node() {
+            self.find(key.id).ok().map(|_| key.id)
+        } else {
+            self.relayed_ids.get(&key).copied()
+        }
+    }
+
+    /// Makes a message a reply to a message in the history
+    pub fn attach_reply(
+        &self,
+        message: &mut ChatMessage,
+        reply_to: MessageId,
+    ) -> Result<(), ServerError> {
+        let parent = self.find(reply_to)?;
+
+        message.reply_to = Some(Quote::new(
+            reply_to,
+            parent.sender_name.clone(),
+            parent.content.clone(),
+        ));
+        message.thread = Some(parent.thread.unwrap_or(reply_to));
+
+        Ok(())
+    }
+
+    /// Assigns an ID to a message and stores it in the history
+    fn record(&mut self, mut message: ChatMessage) -> (MessageId, ChatMessage) {
+        let id = self.next_message_id;
+        self.next_message_id += 1;
+        message.id = Some(id);
+
+        // Without a history the message is only broadcast
+        if self.history_len == 0 {
+            return (id, message);
+        }
+
+        while self.history.len() >= self.history_len {
+            let Some(dropped) = self.history.pop_front() else {
+                break;
+            };
+            if let Some(dropped_id) = dropped.id {
+                self.reactions.remove(&dropped_id);
+                self.index.remove(dropped_id, &dropped.content);
+                if let Some(key) = self.keys.remove(&dropped_id) {
+                    self.relayed_ids.remove(&key);
+                }
+            }
+        }
+        self.index.insert(id, &message.content);
+        self.history.push_back(message.clone());
+
+        (id, message)
+    }
+
+    /// Assigns an ID to a message, stores it in the history and broadcasts it
+    pub fn post(&mut self, message: ChatMessage) -> MessageId {
+        let (id, message) = self.record(message);
+
+        // Other instances number their history themselves, so references are sent as keys
+        if self.cluster.has_peers() {
+            self.cluster.relay(PeerMessage::Posted {
+                room: self.name.clone(),
+                key: self.key_of(id),
+                reply_to: message.reply_to.as_ref().map(|quote| self.key_of(quote.id)),
+                thread: message.thread.map(|thread| self.key_of(thread)),
+                message: message.clone(),
+            });
+        }
+        let _ = self.send.send(Broadcast::Message(message));
+        id
+    }
+
+    /// Passes on something broadcast on another instance of the cluster to the members on
+    /// this one
+    pub fn relayed(&self, broadcast: Broadcast) {
+        let _ = self.send.send(broadcast);
+    }
+
+    /// Stores a message posted on another instance of the cluster in the history under an ID
+    /// of this instance and passes it on to the members on this one
+    pub fn relayed_post(
+        &mut self,
+        key: MessageKey,
+        reply_to: Option<MessageKey>,
+        thread: Option<MessageKey>,
+        mut message: ChatMessage,
+    ) {
+        // Messages that already dropped out of the history cannot be referred to
+        let reply_id = reply_to.and_then(|key| self.id_of(key));
+        message.reply_to = message.reply_to.zip(reply_id).map(|(mut quote, id)| {
+            quote.id = id;
+            quote
+        });
+        message.thread = thread.and_then(|key| self.id_of(key));
+
+        let (id, message) = self.record(message);
+        if self.find(id).is_ok() {
+            self.keys.insert(id, key);
+            self.relayed_ids.insert(key, id);
+        }
+        let _ = self.send.send(Broadcast::Message(message));
+    }
+
+    /// ID of the latest posted message
+    pub fn last_id(&self) -> Option<MessageId> {
+        self.next_message_id.checked_sub(1)
+    }
+
+    /// Counts the messages in the history posted after the given one
+    pub fn unread_since(&self, marker: Option<MessageId>) -> usize {
+        self.history
+            .iter()
+            .filter(|message| message.id > marker)
+            .count()
+    }
+
+    /// Counts the reactions to a message
+    fn reaction_counts(&self, id: MessageId) -> Vec<ReactionCount> {
+        self.reactions
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|(emoji, users)| ReactionCount::new(emoji.clone(), users.len()))
+            .collect()
+    }
+
+    /// Converts a message from the history for a client, including its reactions
+    fn to_client(&self, message: &ChatMessage) -> NewMessageRequest {
+        let mut new_message = NewMessageRequest::from(message.clone());
+        if let Some(id) = message.id {
+            new_message.reactions = self.reaction_counts(id);
+        }
+        new_message
+    }
+
+    /// Returns all messages of the thread the given message is part of, oldest first
+    pub fn thread(&self, id: MessageId) -> Result<Vec<NewMessageRequest>, ServerError> {
+        let root = self.find(id)?.thread.unwrap_or(id);
+
+        Ok(self
+            .history
+            .iter()
+            .filter(|message| message.id == Some(root) || message.thread == Some(root))
+            .map(|message| self.to_client(message))
+            .collect())
+    }
+
+    /// Returns a page of the history, oldest first, and the ID to continue before if there are
+    /// older messages
+    pub fn history(
+        &self,
+        before_id: Option<MessageId>,
+        limit: usize,
+    ) -> (Vec<NewMessageRequest>, Option<MessageId>) {
+        let mut older = self
+            .history
+            .iter()
+            .rev()
+            .skip_while(|message| before_id.is_some_and(|before| message.id >= Some(before)));
+
+        let mut page: Vec<NewMessageRequest> = older
+            .by_ref()
+            .take(limit)
+            .map(|message| self.to_client(message))
+            .collect();
+        page.reverse();
+        let next = older
+            .next()
+            .and(page.first().and_then(|message| message.id));
+
+        (page, next)
+    }
+
+    /// Searches the history, returns a page of matches, newest first, and the ID to continue
+    /// before if there are more
+    pub fn search(
+        &self,
+        request: &SearchRequest,
+        limit: usize,
+    ) -> (Vec<NewMessageRequest>, Option<MessageId>) {
+        // Without words, every message in the history is narrowed down by the other criteria
+        let ids = self.index.search(&request.query).unwrap_or_else(|| {
+            self.history
+                .iter()
+                .filter_map(|message| message.id)
+                .collect()
+        });
+        let mut matches = ids
+            .range(..request.before_id.unwrap_or(MessageId::MAX))
+            .rev()
+            .filter_map(|&id| self.find(id).ok())
+            .filter(|message| {
+                request
+                    .author
+                    .as_ref()
+                    .is_none_or(|author| &message.sender_name == author)
+            })
+            .filter(|message| request.since.is_none_or(|since| message.sent_at >= since))
+            .filter(|message| request.until.is_none_or(|until| message.sent_at < until));
+
+        let page: Vec<NewMessageRequest> = matches
+            .by_ref()
+            .take(limit)
+            .map(|message| self.to_client(message))
+            .collect();
+        let next = matches
+            .next()
+            .and(page.last().and_then(|message| message.id));
+
+        (page, next)
+    }
+
+    /// Adds or removes the reaction of a connection to a message, broadcasts the change
+    pub fn react(
+        &mut self,
+        id: MessageId,
+        emoji: String,
+        uuid: Uuid,
+        add: bool,
+    ) -> Result<(), ServerError> {
+        if self.toggle_reaction(id, emoji.clone(), uuid, add)? {
+            self.cluster.relay(PeerMessage::Reacted {
+                room: self.name.clone(),
+                key: self.key_of(id),
+                emoji,
+                uuid,
+                add,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Applies a reaction added or removed on another instance of the cluster
+    pub fn relayed_reaction(&mut self, key: MessageKey, emoji: String, uuid: Uuid, add: bool) {
+        if let Some(id) = self.id_of(key) {
+            let _ = self.toggle_reaction(id, emoji, uuid, add);
+        }
+    }
+
+    /// Adds or removes a reaction, broadcasts the change to the members on this instance,
+    /// returns whether anything changed
+    fn toggle_reaction(
+        &mut self,
+        id: MessageId,
+        emoji: String,
+        uuid: Uuid,
+        add: bool,
+    ) -> Result<bool, ServerError> {
+        self.find(id)?;
+
+        let reactions = self.reactions.entry(id).or_default();
+        if add && reactions.len() >= MAX_REACTIONS && !reactions.contains_key(&emoji) {
+            return Err(ServerError::new(ErrorCode::TooManyReactions)
+                .details(format!("at most {MAX_REACTIONS} per message")));
+        }
+
+        let changed = if add {
+            reactions.entry(emoji).or_default().insert(uuid)
+        } else {
+            let removed = reactions
+                .get_mut(&emoji)
+                .is_some_and(|users| users.remove(&uuid));
+            reactions.retain(|_, users| !users.is_empty());
+            removed
+        };
+
+        if changed {
+            let update = ReactionsResponse::new(id, self.reaction_counts(id));
+            let _ = self.send.send(Broadcast::Reactions(update));
+        }
+
+        Ok(changed)
+    }
+
     /// Publish to a chatroom
-    pub fn publish(&self) -> broadcast::Sender<ChatMessage> {
+    pub fn publish(&self) -> broadcast::Sender<Broadcast> {
         self.send.clone()
     }
+
+    /// Number of handlers subscribed to the chatroom
+    pub fn members(&self) -> usize {
+        self.send.receiver_count()
+    }
+
+    /// Checks whether the given connection, or the identity it is logged in to, created the
+    /// chatroom
+    pub fn is_owner(&self, uuid: &Uuid, identity: Option<&str>) -> bool {
+        match &self.owner {
+            Some(Owner::Connection(owner)) => owner == uuid,
+            Some(Owner::Identity(owner)) => identity == Some(owner.as_str()),
+            None => false,
+        }
+    }
+
+    /// Replaces the content filter, none removes it
+    pub fn set_filter(&mut self, filter: Option<ContentFilter>) {
+        self.filter = filter;
+    }
+
+    /// Applies the content filter to a message, returns the message to broadcast
+    pub fn filter(&mut self, mut message: ChatMessage) -> Result<ChatMessage, ServerError> {
+        let Some(filter) = &self.filter else {
+            return Ok(message);
+        };
+
+        if !filter.is_match(&message.content) {
+            return Ok(message);
+        }
+
+        match filter.mode() {
+            FilterMode::Reject => Err(ServerError::new(ErrorCode::MessageBlocked)),
+            FilterMode::Mask => {
+                message.content = filter.mask(&message.content);
+                Ok(message)
+            }
+            FilterMode::Hold if self.held.len() >= MAX_HELD => {
+                Err(ServerError::new(ErrorCode::MessageBlocked)
+                    .details(format!("{MAX_HELD} messages already wait for review")))
+            }
+            FilterMode::Hold => {
+                let id = self.next_held_id;
+                self.next_held_id += 1;
+                self.held.insert(id, message);
+
+                Err(ServerError::new(ErrorCode::MessageHeld).details(id.to_string()))
+            }
+        }
+    }
+
+    /// IDs of the attachments of the messages in the history or waiting for review
+    pub fn attachments(&self) -> impl Iterator<Item = &str> {
+        self.history
+            .iter()
+            .chain(self.held.values())
+            .filter_map(|message| message.attachment.as_ref())
+            .map(|attachment| attachment.id.as_str())
+    }
+
+    /// Lists the messages waiting for review
+    pub fn held(&self) -> Vec<HeldMessage> {
+        self.held
+            .iter()
+            .map(|(id, message)| {
+                HeldMessage::new(*id, message.sender_name.clone(), message.content.clone())
+            })
+            .collect()
+    }
+
+    /// Broadcasts or discards a held message
+    pub fn review(&mut self, id: u64, approve: bool) -> Result<(), ServerError> {
+        let message = self.held.remove(&id).ok_or_else(|| {
+            ServerError::new(ErrorCode::HeldMessageNotFound).details(id.to_string())
+        })?;
+
+        if approve {
+            self.post(message);
+        }
+
+        Ok(())
+    }
+}
+
+/// Instructions for a handler that do not originate from its own client
+#[derive(Debug)]
+pub enum HandlerCommand {
+    /// Forward a server-wide announcement to the client
+    Announce(String),
+    /// Forward a direct message to the client
+    DirectMessage(DirectMessageResponse),
+    /// Leave the given chatroom because it was deleted
+    RoomDeleted(String),
+    /// Close the connection to the client
+    Disconnect,
+}
+
+/// Publicly visible information on a connected client
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+    pub uuid: Uuid,
+    pub name: String,
+    /// Registered identity the client logged in as
+    pub identity: Option<String>,
+    pub addr: SocketAddr,
+    pub room: Option<String>,
+    pub presence: Presence,
+    /// Message the client shows next to its presence
+    pub status: Option<String>,
+}
+
+/// A client connected to the server
+pub struct Connection {
+    /// Information on the client
+    info: ConnectionInfo,
+    /// Channel to instruct the handler of the client
+    control: mpsc::UnboundedSender<HandlerCommand>,
+}
+
+/// Publicly visible information on a chatroom
+#[derive(Clone, Debug, Serialize)]
+pub struct RoomInfo {
+    pub name: String,
+    pub members: usize,
+}
+
+/// Snapshot of the backend state
+#[derive(Debug, Serialize)]
+pub struct BackendSnapshot {
+    pub rooms: Vec<RoomInfo>,
+    pub connections: Vec<ConnectionInfo>,
+}
+
+/// Clients connected to another instance of the cluster
+struct RemoteNode {
+    /// ID of the instance
+    node: Uuid,
+    /// Connected clients by their ID
+    members: HashMap<Uuid, ConnectionInfo>,
 }
 
 /// Datastructure that keep tracks of all chatrooms
 pub struct Backend {
     rooms: HashMap<String, ChatRoom>,
+    /// All connected clients by their ID
+    connections: HashMap<Uuid, Connection>,
+    /// Clients of the other instances of the cluster, by the link to their instance
+    remote: HashMap<Uuid, RemoteNode>,
+    /// Relays rooms and membership to the other instances of the cluster
+    cluster: Cluster,
+    /// Capacity of the broadcast channel of new chatrooms
+    room_capacity: usize,
+    /// Number of messages new chatrooms keep in their history
+    history_len: usize,
+    /// Registered identities
+    accounts: Accounts,
+    /// Storage for attachments, none if the server has no data directory
+    blobs: Option<BlobStore>,
 }
 
 impl Backend {
     /// Crates a new backend
-    pub fn new() -> Self {
+    pub fn new(
+        room_capacity: usize,
+        history_len: usize,
+        accounts: Accounts,
+        blobs: Option<BlobStore>,
+        cluster: Cluster,
+    ) -> Self {
         Self {
             rooms: HashMap::new(),
+            connections: HashMap::new(),
+            remote: HashMap::new(),
+            cluster,
+            room_capacity,
+            history_len,
+            accounts,
+            blobs,
         }
     }
 
-    /// Creates a new chatroom
-    pub fn new_room(&mut self, name: String) -> Result<()> {
+    /// Storage for attachments, none if attachments are disabled
+    pub fn blobs(&self) -> Option<BlobStore> {
+        self.blobs.clone()
+    }
+
+    /// IDs of the attachments any chatroom still refers to
+    pub fn attachments(&self) -> HashSet<String> {
+        self.rooms
+            .values()
+            .flat_map(ChatRoom::attachments)
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Registered identities
+    pub fn accounts(&self) -> &Accounts {
+        &self.accounts
+    }
+
+    /// Registered identities for modification
+    pub fn accounts_mut(&mut self) -> &mut Accounts {
+        &mut self.accounts
+    }
+
+    /// Creates a new chatroom owned by the given client
+    pub fn new_room(&mut self, name: String, owner: Option<Owner>) -> Result<(), ServerError> {
         match self.rooms.get(&name) {
-            Some(_) => Err(miette!("Room already exists")),
+            Some(_) => Err(ServerError::new(ErrorCode::RoomExists).details(name)),
             None => {
-                let room = ChatRoom::new();
-                self.rooms.insert(name, room);
+                let room = ChatRoom::new(
+                    name.clone(),
+                    self.room_capacity,
+                    self.history_len,
+                    owner,
+                    self.cluster.clone(),
+                );
+                self.rooms.insert(name.clone(), room);
+                self.cluster.relay(PeerMessage::RoomCreated(name));
                 Ok(())
             }
         }
     }
 
+    /// Creates a chatroom unless it already exists
+    pub fn ensure_room(&mut self, name: String) {
+        let (capacity, history_len) = (self.room_capacity, self.history_len);
+        let cluster = &self.cluster;
+        self.rooms.entry(name).or_insert_with_key(|name| {
+            ChatRoom::new(name.clone(), capacity, history_len, None, cluster.clone())
+        });
+    }
+
     /// Returns a requested chatroom
-    pub fn get_room(&self, name: String) -> Result<&ChatRoom> {
+    pub fn get_room(&self, name: String) -> Result<&ChatRoom, ServerError> {
         let room = self
             .rooms
             .get(&name)
-            .ok_or(miette!("Could not find room"))?;
+            .ok_or_else(|| ServerError::new(ErrorCode::RoomNotFound).details(name.clone()))?;
+
+        Ok(room)
+    }
 
-        return Ok(room);
+    /// Returns a requested chatroom for modification
+    pub fn get_room_mut(&mut self, name: &str) -> Result<&mut ChatRoom, ServerError> {
+        self.rooms
+            .get_mut(name)
+            .ok_or_else(|| ServerError::new(ErrorCode::RoomNotFound).details(name))
     }
 
     /// Lists the names of all chatrooms
     pub fn list(&self) -> Vec<String> {
         self.rooms.keys().cloned().collect()
     }
+
+    /// Deletes a chatroom on all instances of the cluster
+    pub fn delete_room(&mut self, name: &str) -> Result<(), ServerError> {
+        self.remove_room(name)?;
+        self.cluster
+            .relay(PeerMessage::RoomDeleted(name.to_string()));
+
+        Ok(())
+    }
+
+    /// Deletes a chatroom and makes all its members leave it
+    pub fn remove_room(&mut self, name: &str) -> Result<(), ServerError> {
+        self.rooms
+            .remove(name)
+            .ok_or_else(|| ServerError::new(ErrorCode::RoomNotFound).details(name))?;
+
+        for connection in self.connections.values() {
+            if connection.info.room.as_deref() == Some(name) {
+                let _ = connection
+                    .control
+                    .send(HandlerCommand::RoomDeleted(name.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists all chatrooms with their number of members
+    pub fn rooms(&self) -> Vec<RoomInfo> {
+        self.rooms
+            .iter()
+            .map(|(name, room)| RoomInfo {
+                name: name.clone(),
+                members: room.members(),
+            })
+            .collect()
+    }
+
+    /// Registers a newly connected client
+    pub fn connect(
+        &mut self,
+        info: ConnectionInfo,
+        control: mpsc::UnboundedSender<HandlerCommand>,
+    ) {
+        let uuid = info.uuid;
+        self.connections.insert(uuid, Connection { info, control });
+        self.relay_member(&uuid);
+    }
+
+    /// Removes a client that disconnected
+    pub fn disconnect(&mut self, uuid: &Uuid) {
+        self.connections.remove(uuid);
+        self.cluster.relay(PeerMessage::Disconnected(*uuid));
+    }
+
+    /// Tells the other instances of the cluster about a change of a connected client
+    fn relay_member(&self, uuid: &Uuid) {
+        if let Some(connection) = self.connections.get(uuid) {
+            self.cluster
+                .relay(PeerMessage::Member(connection.info.clone()));
+        }
+    }
+
+    /// Adds the clients of another instance of the cluster, replaces those it had on an
+    /// earlier link
+    pub fn add_remote_node(
+        &mut self,
+        link: Uuid,
+        node: Uuid,
+        rooms: Vec<String>,
+        members: Vec<ConnectionInfo>,
+    ) {
+        for room in rooms {
+            self.ensure_room(room);
+        }
+
+        self.remote.retain(|_, remote| remote.node != node);
+        let members = members
+            .into_iter()
+            .map(|member| (member.uuid, member))
+            .collect();
+        self.remote.insert(link, RemoteNode { node, members });
+    }
+
+    /// Removes the clients of an instance of the cluster that is no longer linked
+    pub fn remove_remote_node(&mut self, link: Uuid) -> Vec<ConnectionInfo> {
+        self.remote
+            .remove(&link)
+            .map(|remote| remote.members.into_values().collect())
+            .unwrap_or_default()
+    }
+
+    /// Adds or updates a client of another instance of the cluster
+    pub fn update_remote_member(&mut self, link: Uuid, info: ConnectionInfo) {
+        if let Some(remote) = self.remote.get_mut(&link) {
+            remote.members.insert(info.uuid, info);
+        }
+    }
+
+    /// Removes a client of another instance of the cluster that disconnected
+    pub fn remove_remote_member(&mut self, link: Uuid, uuid: &Uuid) {
+        if let Some(remote) = self.remote.get_mut(&link) {
+            remote.members.remove(uuid);
+        }
+    }
+
+    /// Clients of the other instances of the cluster
+    fn remote_members(&self) -> impl Iterator<Item = &ConnectionInfo> {
+        self.remote
+            .values()
+            .flat_map(|remote| remote.members.values())
+    }
+
+    /// Updates the name of a connected client
+    pub fn set_name(&mut self, uuid: &Uuid, name: String) {
+        if let Some(connection) = self.connections.get_mut(uuid) {
+            connection.info.name = name;
+        }
+        self.relay_member(uuid);
+    }
+
+    /// Updates the identity a connected client logged in as
+    pub fn set_identity(&mut self, uuid: &Uuid, identity: String) {
+        if let Some(connection) = self.connections.get_mut(uuid) {
+            connection.info.name = identity.clone();
+            connection.info.identity = Some(identity);
+        }
+        self.relay_member(uuid);
+    }
+
+    /// Checks whether a client other than the given connection uses a name, on any instance of
+    /// the cluster
+    pub fn is_name_used(&self, uuid: &Uuid, name: &str) -> bool {
+        self.connections
+            .values()
+            .map(|connection| &connection.info)
+            .chain(self.remote_members())
+            .any(|info| info.name == name && info.uuid != *uuid)
+    }
+
+    /// Checks whether a name is registered to someone other than the given connection
+    pub fn is_name_taken(&self, uuid: &Uuid, name: &str) -> bool {
+        let own = self
+            .connections
+            .get(uuid)
+            .and_then(|connection| connection.info.identity.as_deref());
+
+        self.accounts.is_registered(name) && own != Some(name)
+    }
+
+    /// Counts the unread messages of an identity in every chatroom
+    pub fn unread(&self, identity: &str) -> BTreeMap<String, usize> {
+        self.rooms
+            .iter()
+            .map(|(name, room)| {
+                let marker = self.accounts.read_marker(identity, name);
+                (name.clone(), room.unread_since(marker))
+            })
+            .collect()
+    }
+
+    /// Delivers a direct message to every connection of a user, queues it if the user is
+    /// registered but offline, handlers drop it if the user ignores the sender
+    pub fn direct_message(
+        &mut self,
+        to: &str,
+        message: DirectMessageResponse,
+    ) -> Result<(), ServerError> {
+        // The sender is not told, as if the message was delivered
+        let from = message.from_identity.as_deref();
+        if from.is_some_and(|from| self.accounts.is_ignoring(to, from)) {
+            return Ok(());
+        }
+
+        // Anyone may use the name of an identity that is not logged in, so registered names
+        // only reach the clients logged in as them
+        let registered = self.accounts.is_registered(to);
+        let delivered = self
+            .connections
+            .values()
+            .filter(|connection| {
+                if registered {
+                    connection.info.identity.as_deref() == Some(to)
+                } else {
+                    connection.info.name == to
+                }
+            })
+            .filter(|connection| {
+                connection
+                    .control
+                    .send(HandlerCommand::DirectMessage(message.clone()))
+                    .is_ok()
+            })
+            .count();
+
+        if delivered > 0 {
+            return Ok(());
+        }
+
+        self.accounts.queue(to, message)
+    }
+
+    /// Updates the chatroom of a connected client
+    pub fn set_room(&mut self, uuid: &Uuid, room: Option<String>) {
+        if let Some(connection) = self.connections.get_mut(uuid) {
+            connection.info.room = room;
+        }
+        self.relay_member(uuid);
+    }
+
+    /// Updates the presence of a connected client
+    pub fn set_presence(&mut self, uuid: &Uuid, presence: Presence, status: Option<String>) {
+        if let Some(connection) = self.connections.get_mut(uuid) {
+            connection.info.presence = presence;
+            connection.info.status = status;
+        }
+        self.relay_member(uuid);
+    }
+
+    /// Lists the members of a chatroom on all instances of the cluster with their presence,
+    /// sorted by name
+    pub fn who(&self, room: &str) -> Vec<PresenceResponse> {
+        let mut users: Vec<PresenceResponse> = self
+            .connections
+            .values()
+            .map(|connection| &connection.info)
+            .chain(self.remote_members())
+            .filter(|info| info.room.as_deref() == Some(room))
+            .map(|info| {
+                PresenceResponse::new(
+                    info.name.clone(),
+                    info.identity.clone(),
+                    info.presence,
+                    info.status.clone(),
+                )
+            })
+            .collect();
+        users.sort_by(|a, b| a.user_name.cmp(&b.user_name));
+
+        users
+    }
+
+    /// Checks whether a client with the given name is connected to any instance of the cluster
+    pub fn has_name(&self, name: &str) -> bool {
+        self.connections
+            .values()
+            .any(|connection| connection.info.name == name)
+            || self.remote_members().any(|info| info.name == name)
+    }
+
+    /// Lists all connected clients
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.connections
+            .values()
+            .map(|connection| connection.info.clone())
+            .collect()
+    }
+
+    /// Sends an announcement to all connected clients, returns how many were reached
+    pub fn announce(&self, content: &str) -> usize {
+        self.connections
+            .values()
+            .filter(|connection| {
+                connection
+                    .control
+                    .send(HandlerCommand::Announce(content.to_string()))
+                    .is_ok()
+            })
+            .count()
+    }
+
+    /// Disconnects all clients matching the given ID or name, returns how many were matched
+    pub fn kick(&self, who: &str) -> usize {
+        self.connections
+            .values()
+            .filter(|connection| {
+                connection.info.uuid.to_string() == who || connection.info.name == who
+            })
+            .filter(|connection| connection.control.send(HandlerCommand::Disconnect).is_ok())
+            .count()
+    }
+
+    /// Takes a snapshot of all rooms and connections
+    pub fn snapshot(&self) -> BackendSnapshot {
+        BackendSnapshot {
+            rooms: self.rooms(),
+            connections: self.connections(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room(history_len: usize, messages: &[(&str, &str)]) -> ChatRoom {
+        let mut room = ChatRoom::new(
+            "lobby".to_string(),
+            16,
+            history_len,
+            None,
+            Cluster::default(),
+        );
+        for (sender, content) in messages {
+            room.post(ChatMessage::new(
+                String::new(),
+                sender.to_string(),
+                content.to_string(),
+            ));
+        }
+        room
+    }
+
+    fn ids(page: &[NewMessageRequest]) -> Vec<MessageId> {
+        page.iter().filter_map(|message| message.id).collect()
+    }
+
+    #[test]
+    fn search_pages_from_the_newest_match() {
+        let messages = [("a", "ping"); 5];
+        let room = room(10, &messages);
+        let request = SearchRequest::new("lobby".to_string(), "ping".to_string());
+
+        let (page, next) = room.search(&request, 2);
+        assert_eq!(ids(&page), [4, 3]);
+        assert_eq!(next, Some(3));
+
+        let request = SearchRequest {
+            before_id: next,
+            ..request
+        };
+        let (page, next) = room.search(&request, 2);
+        assert_eq!(ids(&page), [2, 1]);
+        assert_eq!(next, Some(1));
+
+        let request = SearchRequest {
+            before_id: next,
+            ..request
+        };
+        let (page, next) = room.search(&request, 2);
+        assert_eq!(ids(&page), [0]);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn search_has_no_next_page_when_the_last_one_is_full() {
+        let room = room(10, &[("a", "ping"), ("a", "ping")]);
+        let request = SearchRequest::new("lobby".to_string(), "ping".to_string());
+
+        let (page, next) = room.search(&request, 2);
+        assert_eq!(ids(&page), [1, 0]);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn search_without_words_filters_by_author() {
+        let room = room(10, &[("a", "one"), ("b", "two"), ("a", "three")]);
+        let request = SearchRequest {
+            author: Some("a".to_string()),
+            ..SearchRequest::new("lobby".to_string(), String::new())
+        };
+
+        let (page, next) = room.search(&request, 10);
+        assert_eq!(ids(&page), [2, 0]);
+        assert_eq!(next, None);
+    }
+
+    #[test]
+    fn search_skips_messages_that_left_the_history() {
+        let room = room(2, &[("a", "ping"), ("a", "ping"), ("a", "ping")]);
+        let request = SearchRequest::new("lobby".to_string(), "ping".to_string());
+
+        let (page, _) = room.search(&request, 10);
+        assert_eq!(ids(&page), [2, 1]);
+    }
 }
@@ -0,0 +1,269 @@
+use miette::{IntoDiagnostic, Result, WrapErr};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs::File, io::AsyncWriteExt, sync::RwLock};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::server::{
+    backend::Backend,
+    communication::{
+        client::StartUploadRequest,
+        error::{ErrorCode, ServerError},
+        server::Attachment,
+        to_hex,
+    },
+};
+
+/// Maximum number of uploads a client may have in progress at the same time
+pub const MAX_UPLOADS: usize = 4;
+
+/// Time between two looks for attachments no message refers to anymore
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Age below which attachments are kept even if no message refers to them, so an upload can
+/// be attached to a message after it finished
+const EXPIRY_GRACE: Duration = Duration::from_secs(60 * 60);
+
+/// Attachments stored on disk, named by their checksum so equal files are stored once
+#[derive(Clone, Debug)]
+pub struct BlobStore {
+    /// Directory the attachments are stored in, unfinished uploads are kept in `tmp` below it
+    dir: PathBuf,
+    /// Bytes taken by stored attachments and reserved by uploads in progress
+    used: Arc<Mutex<u64>>,
+}
+
+impl BlobStore {
+    /// Opens the directory attachments are stored in, creates it if it is missing and discards
+    /// uploads that were in progress when the server stopped
+    pub fn open(dir: PathBuf) -> Result<Self> {
+        let tmp = dir.join("tmp");
+        if tmp.exists() {
+            fs::remove_dir_all(&tmp)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Could not clean up {}", tmp.display()))?;
+        }
+        fs::create_dir_all(&tmp)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not create {}", tmp.display()))?;
+
+        let mut used = 0;
+        for entry in fs::read_dir(&dir).into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            if Self::is_valid_id(&entry.file_name().to_string_lossy()) {
+                used += entry.metadata().into_diagnostic()?.len();
+            }
+        }
+
+        Ok(Self {
+            dir,
+            used: Arc::new(Mutex::new(used)),
+        })
+    }
+
+    /// Checks whether an attachment ID is a checksum, which makes it safe to use as a file name
+    pub fn is_valid_id(id: &str) -> bool {
+        id.len() == 64 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    }
+
+    /// Path of a stored attachment
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    /// Checks whether an attachment is stored, it may have expired since it was uploaded
+    pub async fn contains(&self, id: &str) -> bool {
+        Self::is_valid_id(id) && tokio::fs::try_exists(self.path(id)).await.unwrap_or(false)
+    }
+
+    /// Starts receiving a file into a temporary file, reserves its size unless the store would
+    /// grow beyond the given quota
+    pub async fn upload(
+        &self,
+        request: StartUploadRequest,
+        quota: u64,
+    ) -> Result<Result<Upload, ServerError>> {
+        {
+            let mut used = self.used.lock().unwrap();
+            if *used + request.size > quota {
+                return Ok(Err(ServerError::new(ErrorCode::StorageFull)
+                    .details(format!("{} of {quota} bytes are used", *used))));
+            }
+            *used += request.size;
+        }
+
+        let temp_path = self.dir.join("tmp").join(Uuid::new_v4().to_string());
+        let file = match File::create(&temp_path).await {
+            Ok(file) => file,
+            Err(err) => {
+                self.release(request.size);
+                return Err(err)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Could not create {}", temp_path.display()));
+            }
+        };
+
+        Ok(Ok(Upload {
+            file,
+            temp_path,
+            file_name: request.file_name,
+            size: request.size,
+            received: 0,
+            hasher: Sha256::new(),
+            checksum: request.checksum,
+            store: self.clone(),
+        }))
+    }
+
+    /// Gives back space that was reserved or taken by an attachment
+    fn release(&self, size: u64) {
+        let mut used = self.used.lock().unwrap();
+        *used = used.saturating_sub(size);
+    }
+
+    /// Deletes the attachments that are not referred to and older than `EXPIRY_GRACE`, returns
+    /// how many were deleted
+    pub async fn expire(&self, referenced: &HashSet<String>) -> Result<usize> {
+        let mut expired = 0;
+        let mut entries = tokio::fs::read_dir(&self.dir).await.into_diagnostic()?;
+
+        while let Some(entry) = entries.next_entry().await.into_diagnostic()? {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if !Self::is_valid_id(&id) || referenced.contains(&id) {
+                continue;
+            }
+
+            let metadata = entry.metadata().await.into_diagnostic()?;
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+            if age.is_some_and(|age| age < EXPIRY_GRACE) {
+                continue;
+            }
+
+            tokio::fs::remove_file(entry.path())
+                .await
+                .into_diagnostic()?;
+            self.release(metadata.len());
+            expired += 1;
+        }
+
+        Ok(expired)
+    }
+}
+
+/// Deletes attachments that no message in the history of a chatroom refers to anymore, every
+/// `EXPIRY_INTERVAL`
+pub async fn expire(store: BlobStore, backend: Arc<RwLock<Backend>>) {
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let referenced = backend.read().await.attachments();
+        match store.expire(&referenced).await {
+            Ok(0) => {}
+            Ok(expired) => info!(expired, "Deleted attachments no message refers to"),
+            Err(report) => warn!(error = %report, "Failed to delete expired attachments"),
+        }
+    }
+}
+
+/// File that is being uploaded by a client
+pub struct Upload {
+    /// Temporary file the chunks are written to
+    file: File,
+    /// Path of the temporary file
+    temp_path: PathBuf,
+    /// Name of the file on the side of the client
+    file_name: String,
+    /// Announced size in bytes
+    size: u64,
+    /// Number of bytes received so far
+    received: u64,
+    /// Checksum of the bytes received so far
+    hasher: Sha256,
+    /// Announced checksum
+    checksum: String,
+    /// Store the upload reserved its size in
+    store: BlobStore,
+}
+
+impl Upload {
+    /// Appends a chunk, which has to continue where the last one ended
+    pub async fn write(&mut self, offset: u64, data: &[u8]) -> Result<Result<(), ServerError>> {
+        if offset != self.received {
+            return Ok(Err(ServerError::new(ErrorCode::InvalidUpload)
+                .details(format!("expected offset {}", self.received))));
+        }
+
+        let received = self.received + data.len() as u64;
+        if received > self.size {
+            return Ok(Err(ServerError::new(ErrorCode::InvalidUpload)
+                .details(format!("announced {} bytes", self.size))));
+        }
+
+        self.file.write_all(data).await.into_diagnostic()?;
+        self.hasher.update(data);
+        self.received = received;
+
+        Ok(Ok(()))
+    }
+
+    /// Checks the received file against what was announced and moves it into the store
+    pub async fn finish(mut self) -> Result<Result<Attachment, ServerError>> {
+        if let Err(err) = self.file.flush().await {
+            self.abort().await;
+            return Err(err).into_diagnostic();
+        }
+
+        if self.received != self.size {
+            let error = ServerError::new(ErrorCode::InvalidUpload)
+                .details(format!("received {} of {} bytes", self.received, self.size));
+            self.abort().await;
+            return Ok(Err(error));
+        }
+
+        let checksum = to_hex(&self.hasher.finalize_reset());
+        if checksum != self.checksum {
+            self.abort().await;
+            return Ok(Err(ServerError::new(ErrorCode::ChecksumMismatch)));
+        }
+
+        if let Err(err) = self.store_as(&checksum).await {
+            self.abort().await;
+            return Err(err).into_diagnostic();
+        }
+
+        Ok(Ok(Attachment::new(checksum, self.file_name, self.size)))
+    }
+
+    /// Moves the received file into the store under its checksum
+    async fn store_as(&self, checksum: &str) -> io::Result<()> {
+        let path = self.store.path(checksum);
+        let replaced = tokio::fs::try_exists(&path).await?;
+        tokio::fs::rename(&self.temp_path, path).await?;
+
+        // An equal file that was stored already is replaced, so its space is only taken once
+        if replaced {
+            self.store.release(self.size);
+        }
+
+        Ok(())
+    }
+
+    /// Discards the upload and its temporary file, gives back the space it reserved
+    pub async fn abort(self) {
+        self.store.release(self.size);
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.temp_path).await;
+    }
+}
@@ -0,0 +1,376 @@
+use bincode::Options;
+use futures_util::{SinkExt, StreamExt};
+use miette::{miette, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, watch, RwLock},
+};
+use tokio_tungstenite::{
+    accept_async_with_config, connect_async,
+    tungstenite::{protocol::WebSocketConfig, Message},
+    WebSocketStream,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::server::{
+    backend::{Backend, ConnectionInfo},
+    communication::{
+        client::{MessageId, Presence},
+        server::PresenceResponse,
+        Broadcast, ChatMessage,
+    },
+    config::Config,
+};
+
+/// Number of relayed messages a peer link may fall behind before it is reset
+const RELAY_CAPACITY: usize = 1024;
+
+/// Time to wait before connecting to a peer again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Largest message accepted on a peer link, the introduction lists every room and member
+const MAX_PEER_MESSAGE_SIZE: usize = 16 << 20;
+
+/// First message on a peer link, describes the state of the sending instance
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Hello {
+    /// ID of the sending instance
+    pub node: Uuid,
+    /// Secret shared by all instances of the cluster
+    pub secret: Option<String>,
+    /// Names of all chat rooms of the sending instance
+    pub rooms: Vec<String>,
+    /// Clients connected to the sending instance
+    pub members: Vec<ConnectionInfo>,
+}
+
+/// Identifies a message across the cluster, by the instance it was posted on and its ID there
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MessageKey {
+    pub node: Uuid,
+    pub id: MessageId,
+}
+
+/// Everything one instance tells the others about what happened on it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum PeerMessage {
+    Hello(Hello),
+    /// A client created a chat room
+    RoomCreated(String),
+    /// An operator deleted a chat room
+    RoomDeleted(String),
+    /// A client connected or changed its name, room or presence
+    Member(ConnectionInfo),
+    /// A client disconnected
+    Disconnected(Uuid),
+    /// Something that is not stored was sent to the members of a chat room
+    Broadcast(String, Broadcast),
+    /// A message was posted in a chat room, the messages it refers to are given by their key
+    Posted {
+        room: String,
+        key: MessageKey,
+        reply_to: Option<MessageKey>,
+        thread: Option<MessageKey>,
+        message: ChatMessage,
+    },
+    /// A client added or removed a reaction to a message
+    Reacted {
+        room: String,
+        key: MessageKey,
+        emoji: String,
+        uuid: Uuid,
+        add: bool,
+    },
+}
+
+/// Relays what happens on this instance to the other instances of a cluster
+#[derive(Clone)]
+pub struct Cluster {
+    /// ID of this instance
+    node: Uuid,
+    /// Channel every outgoing peer link subscribes to
+    send: broadcast::Sender<PeerMessage>,
+}
+
+impl Default for Cluster {
+    fn default() -> Self {
+        let (send, _) = broadcast::channel(RELAY_CAPACITY);
+
+        Self {
+            node: Uuid::new_v4(),
+            send,
+        }
+    }
+}
+
+impl Cluster {
+    /// ID of this instance
+    pub fn node(&self) -> Uuid {
+        self.node
+    }
+
+    /// Checks whether there are peers to relay to
+    pub fn has_peers(&self) -> bool {
+        self.send.receiver_count() > 0
+    }
+
+    /// Sends a message to all peers, dropped if there are none
+    pub fn relay(&self, message: PeerMessage) {
+        let _ = self.send.send(message);
+    }
+
+    /// Sends something broadcast in a chat room to all peers, without copying it if there are
+    /// none
+    pub fn relay_broadcast(&self, room: &str, broadcast: &Broadcast) {
+        if self.has_peers() {
+            self.relay(PeerMessage::Broadcast(room.to_string(), broadcast.clone()));
+        }
+    }
+}
+
+/// Compares the secret a peer sent in constant time, nobody is accepted without a secret
+fn secret_matches(sent: Option<&str>, expected: Option<&str>) -> bool {
+    let (Some(sent), Some(expected)) = (sent, expected) else {
+        return false;
+    };
+
+    // Digests have the same length, so the time taken reveals nothing about the secret
+    let (sent, expected) = (Sha256::digest(sent), Sha256::digest(expected));
+    sent.iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// Encodes a message for a peer link
+fn encode(message: &PeerMessage) -> Result<Message> {
+    Ok(Message::Binary(
+        bincode::serialize(message).into_diagnostic()?,
+    ))
+}
+
+/// Decodes a message from a peer link
+fn decode(msg: Message) -> Result<PeerMessage> {
+    match msg {
+        Message::Binary(bytes) => bincode::options()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(bytes.len() as u64)
+            .deserialize(&bytes)
+            .into_diagnostic(),
+        _ => Err(miette!("Received an invalid peer message encoding")),
+    }
+}
+
+/// Applies a message from a peer to the backend
+fn apply(backend: &mut Backend, link: Uuid, message: PeerMessage) {
+    match message {
+        // Only the first message on a link introduces the peer
+        PeerMessage::Hello(_) => {}
+        PeerMessage::RoomCreated(name) => backend.ensure_room(name),
+        PeerMessage::RoomDeleted(name) => {
+            let _ = backend.remove_room(&name);
+        }
+        PeerMessage::Member(info) => backend.update_remote_member(link, info),
+        PeerMessage::Disconnected(uuid) => backend.remove_remote_member(link, &uuid),
+        PeerMessage::Broadcast(room, broadcast) => {
+            backend.ensure_room(room.clone());
+            if let Ok(room) = backend.get_room_mut(&room) {
+                room.relayed(broadcast);
+            }
+        }
+        PeerMessage::Posted {
+            room,
+            key,
+            reply_to,
+            thread,
+            message,
+        } => {
+            backend.ensure_room(room.clone());
+            if let Ok(room) = backend.get_room_mut(&room) {
+                room.relayed_post(key, reply_to, thread, message);
+            }
+        }
+        PeerMessage::Reacted {
+            room,
+            key,
+            emoji,
+            uuid,
+            add,
+        } => {
+            if let Ok(room) = backend.get_room_mut(&room) {
+                room.relayed_reaction(key, emoji, uuid, add);
+            }
+        }
+    }
+}
+
+/// Receives what happens on a peer until it disconnects
+async fn receive(
+    ws: WebSocketStream<TcpStream>,
+    cluster: &Cluster,
+    backend: &RwLock<Backend>,
+    config: &watch::Receiver<Config>,
+) -> Result<()> {
+    let (mut ws_send, mut ws_recv) = ws.split();
+
+    let hello = match ws_recv.next().await {
+        Some(msg) => match decode(msg.into_diagnostic()?)? {
+            PeerMessage::Hello(hello) => hello,
+            _ => return Err(miette!("Peer did not introduce itself")),
+        },
+        None => return Ok(()),
+    };
+
+    let expected = config.borrow().cluster_secret.clone();
+    if !secret_matches(hello.secret.as_deref(), expected.as_deref()) {
+        let _ = ws_send.close().await;
+        return Err(miette!("Peer sent the wrong cluster secret"));
+    }
+    if hello.node == cluster.node {
+        let _ = ws_send.close().await;
+        return Err(miette!("Instance is configured as its own peer"));
+    }
+
+    // Links of the same peer that dropped before are replaced
+    let link = Uuid::new_v4();
+    let node = hello.node;
+    info!(%node, "Peer linked");
+    backend
+        .write()
+        .await
+        .add_remote_node(link, node, hello.rooms, hello.members);
+
+    let result = async {
+        while let Some(msg) = ws_recv.next().await {
+            match msg.into_diagnostic()? {
+                Message::Close(_) => break,
+                msg => apply(&mut *backend.write().await, link, decode(msg)?),
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    // Members of a peer that is gone appear to have disconnected
+    let mut backend = backend.write().await;
+    for member in backend.remove_remote_node(link) {
+        if let Some(room) = member.room.as_deref() {
+            if let Ok(room) = backend.get_room_mut(room) {
+                let update =
+                    PresenceResponse::new(member.name, member.identity, Presence::Offline, None);
+                room.relayed(Broadcast::Presence(update));
+            }
+        }
+    }
+    info!(%node, "Peer unlinked");
+
+    result
+}
+
+/// Accepts links from the other instances of the cluster on the given address
+pub async fn serve(
+    addr: SocketAddr,
+    cluster: Cluster,
+    backend: Arc<RwLock<Backend>>,
+    config: watch::Receiver<Config>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await.into_diagnostic()?;
+    info!(%addr, "Accepting peers");
+
+    let ws_config = WebSocketConfig {
+        max_frame_size: Some(MAX_PEER_MESSAGE_SIZE),
+        max_message_size: Some(MAX_PEER_MESSAGE_SIZE),
+        ..Default::default()
+    };
+
+    loop {
+        let (conn, peer) = listener.accept().await.into_diagnostic()?;
+        let cluster = cluster.clone();
+        let backend = backend.clone();
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            let result = match accept_async_with_config(conn, Some(ws_config)).await {
+                Ok(ws) => receive(ws, &cluster, &backend, &config).await,
+                Err(err) => Err(err).into_diagnostic(),
+            };
+
+            if let Err(report) = result {
+                warn!(%peer, error = %report, "Peer link failed");
+            }
+        });
+    }
+}
+
+/// Sends what happens on this instance to a peer until the link drops
+async fn send(
+    addr: SocketAddr,
+    cluster: &Cluster,
+    backend: &RwLock<Backend>,
+    config: &watch::Receiver<Config>,
+) -> Result<()> {
+    let (ws, _) = connect_async(format!("ws://{addr}"))
+        .await
+        .into_diagnostic()?;
+    let (mut ws_send, mut ws_recv) = ws.split();
+
+    // Subscribe first, so nothing that happens while the state is sent gets lost
+    let mut relayed = cluster.send.subscribe();
+    let hello = {
+        let backend = backend.read().await;
+        Hello {
+            node: cluster.node,
+            secret: config.borrow().cluster_secret.clone(),
+            rooms: backend.list(),
+            members: backend.connections(),
+        }
+    };
+    ws_send
+        .send(encode(&PeerMessage::Hello(hello))?)
+        .await
+        .into_diagnostic()?;
+    info!(%addr, "Linked to peer");
+
+    loop {
+        tokio::select! {
+            message = relayed.recv() => match message {
+                Ok(message) => ws_send.send(encode(&message)?).await.into_diagnostic()?,
+                // The peer is resynchronised by a new link
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    let _ = ws_send.close().await;
+                    return Err(miette!("Fell behind by {skipped} messages"));
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            // Peers never send anything back, so this only notices the link closing
+            msg = ws_recv.next() => match msg {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Err(err).into_diagnostic(),
+            },
+        }
+    }
+}
+
+/// Keeps a link to another instance of the cluster, reconnects whenever it drops
+pub async fn connect(
+    addr: SocketAddr,
+    cluster: Cluster,
+    backend: Arc<RwLock<Backend>>,
+    config: watch::Receiver<Config>,
+) {
+    loop {
+        match send(addr, &cluster, &backend, &config).await {
+            Ok(()) => info!(%addr, "Peer closed the link"),
+            Err(report) => warn!(%addr, error = %report, "Link to peer failed"),
+        }
+
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+    }
+}
@@ -1,27 +1,183 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// ID chosen by the client to match responses to its requests
+pub type RequestId = u64;
+
+/// ID of a chat message within the history of its chat room
+pub type MessageId = u64;
+
+/// Wraps every message sent by the client
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClientEnvelope {
+    /// Echoed in the reply to this request, if given
+    pub request_id: Option<RequestId>,
+    pub message: ClientMessage,
+}
+
+impl ClientEnvelope {
+    pub fn new(request_id: Option<RequestId>, message: ClientMessage) -> Self {
+        Self {
+            request_id,
+            message,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ClientMessage {
     SendMessage(SendMessageRequest),
     MakeChatRoom(ClientMakeChatRoomRequest),
     JoinChatRoom(JoinChatRoomRequest),
     ChangeName(ChangeNameRequest),
+    AdminLogin(AdminLoginRequest),
+    Announce(AnnounceRequest),
     ListChatRooms(),
     Help(),
+    SetFilter(SetFilterRequest),
+    ListHeld(),
+    ReviewHeld(ReviewHeldRequest),
+    FetchThread(FetchThreadRequest),
+    React(ReactRequest),
+    Unreact(ReactRequest),
+    Register(CredentialsRequest),
+    Login(CredentialsRequest),
+    DirectMessage(DirectMessageRequest),
+    MarkRead(MarkReadRequest),
+    Search(SearchRequest),
+    FetchHistory(FetchHistoryRequest),
+    StartUpload(StartUploadRequest),
+    UploadChunk(UploadChunkRequest),
+    FinishUpload(FinishUploadRequest),
+    Download(DownloadRequest),
+    /// The user is typing a message, sent repeatedly while it lasts
+    Typing(),
+    SetPresence(SetPresenceRequest),
+    Who(WhoRequest),
+    Ignore(IgnoreRequest),
+    Unignore(IgnoreRequest),
+    ListIgnored(),
+    PublishKey(PublishKeyRequest),
+    FetchKey(FetchKeyRequest),
+    /// Leaves the current chat room without joining another one
+    LeaveChatRoom(),
+}
+
+/// Kinds of client requests, used to refer to a request in a response
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestKind {
+    SendMessage,
+    MakeChatRoom,
+    JoinChatRoom,
+    ChangeName,
+    AdminLogin,
+    Announce,
+    ListChatRooms,
+    Help,
+    SetFilter,
+    ListHeld,
+    ReviewHeld,
+    FetchThread,
+    React,
+    Unreact,
+    Register,
+    Login,
+    DirectMessage,
+    MarkRead,
+    Search,
+    FetchHistory,
+    StartUpload,
+    UploadChunk,
+    FinishUpload,
+    Download,
+    Typing,
+    SetPresence,
+    Who,
+    Ignore,
+    Unignore,
+    ListIgnored,
+    PublishKey,
+    FetchKey,
+    LeaveChatRoom,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl ClientMessage {
+    /// Kind of request of this message
+    pub fn kind(&self) -> RequestKind {
+        match self {
+            ClientMessage::SendMessage(_) => RequestKind::SendMessage,
+            ClientMessage::MakeChatRoom(_) => RequestKind::MakeChatRoom,
+            ClientMessage::JoinChatRoom(_) => RequestKind::JoinChatRoom,
+            ClientMessage::ChangeName(_) => RequestKind::ChangeName,
+            ClientMessage::AdminLogin(_) => RequestKind::AdminLogin,
+            ClientMessage::Announce(_) => RequestKind::Announce,
+            ClientMessage::ListChatRooms() => RequestKind::ListChatRooms,
+            ClientMessage::Help() => RequestKind::Help,
+            ClientMessage::SetFilter(_) => RequestKind::SetFilter,
+            ClientMessage::ListHeld() => RequestKind::ListHeld,
+            ClientMessage::ReviewHeld(_) => RequestKind::ReviewHeld,
+            ClientMessage::FetchThread(_) => RequestKind::FetchThread,
+            ClientMessage::React(_) => RequestKind::React,
+            ClientMessage::Unreact(_) => RequestKind::Unreact,
+            ClientMessage::Register(_) => RequestKind::Register,
+            ClientMessage::Login(_) => RequestKind::Login,
+            ClientMessage::DirectMessage(_) => RequestKind::DirectMessage,
+            ClientMessage::MarkRead(_) => RequestKind::MarkRead,
+            ClientMessage::Search(_) => RequestKind::Search,
+            ClientMessage::FetchHistory(_) => RequestKind::FetchHistory,
+            ClientMessage::StartUpload(_) => RequestKind::StartUpload,
+            ClientMessage::UploadChunk(_) => RequestKind::UploadChunk,
+            ClientMessage::FinishUpload(_) => RequestKind::FinishUpload,
+            ClientMessage::Download(_) => RequestKind::Download,
+            ClientMessage::Typing() => RequestKind::Typing,
+            ClientMessage::SetPresence(_) => RequestKind::SetPresence,
+            ClientMessage::Who(_) => RequestKind::Who,
+            ClientMessage::Ignore(_) => RequestKind::Ignore,
+            ClientMessage::Unignore(_) => RequestKind::Unignore,
+            ClientMessage::ListIgnored() => RequestKind::ListIgnored,
+            ClientMessage::PublishKey(_) => RequestKind::PublishKey,
+            ClientMessage::FetchKey(_) => RequestKind::FetchKey,
+            ClientMessage::LeaveChatRoom() => RequestKind::LeaveChatRoom,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SendMessageRequest {
     pub content: String,
+    /// Message in the current chat room this one replies to
+    pub reply_to: Option<MessageId>,
+    /// File uploaded by the client that is attached to the message
+    pub attachment: Option<AttachmentId>,
 }
 
 impl SendMessageRequest {
     pub fn new(content: String) -> Self {
-        Self { content }
+        Self {
+            content,
+            reply_to: None,
+            attachment: None,
+        }
+    }
+
+    pub fn reply(reply_to: MessageId, content: String) -> Self {
+        Self {
+            content,
+            reply_to: Some(reply_to),
+            attachment: None,
+        }
+    }
+
+    pub fn attach(attachment: AttachmentId, content: String) -> Self {
+        Self {
+            content,
+            reply_to: None,
+            attachment: Some(attachment),
+        }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ClientMakeChatRoomRequest {
     pub name: String,
 }
@@ -32,7 +188,7 @@ impl ClientMakeChatRoomRequest {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JoinChatRoomRequest {
     pub name: String,
 }
@@ -43,7 +199,7 @@ impl JoinChatRoomRequest {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChangeNameRequest {
     pub new_name: String,
 }
@@ -53,3 +209,374 @@ impl ChangeNameRequest {
         Self { new_name }
     }
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AdminLoginRequest {
+    pub password: String,
+}
+
+impl AdminLoginRequest {
+    pub fn new(password: String) -> Self {
+        Self { password }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnnounceRequest {
+    pub content: String,
+}
+
+impl AnnounceRequest {
+    pub fn new(content: String) -> Self {
+        Self { content }
+    }
+}
+
+/// What happens to messages that match the filter of a chat room
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterMode {
+    /// Refuse the message with an error
+    Reject,
+    /// Replace the matched text with asterisks
+    Mask,
+    /// Keep the message back until a moderator approves it
+    Hold,
+}
+
+/// Content filter of a chat room
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilterSettings {
+    pub mode: FilterMode,
+    /// Blocked words, matched case insensitively as whole words
+    pub words: Vec<String>,
+    /// Blocked regular expressions
+    pub patterns: Vec<String>,
+}
+
+impl FilterSettings {
+    pub fn new(mode: FilterMode, words: Vec<String>, patterns: Vec<String>) -> Self {
+        Self {
+            mode,
+            words,
+            patterns,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetFilterRequest {
+    /// Filter for the current chat room, none removes the filter
+    pub filter: Option<FilterSettings>,
+}
+
+impl SetFilterRequest {
+    pub fn new(filter: Option<FilterSettings>) -> Self {
+        Self { filter }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewHeldRequest {
+    /// ID of the held message in the current chat room
+    pub id: u64,
+    /// Whether to broadcast the message, otherwise it is discarded
+    pub approve: bool,
+}
+
+impl ReviewHeldRequest {
+    pub fn new(id: u64, approve: bool) -> Self {
+        Self { id, approve }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchThreadRequest {
+    /// Any message of the thread in the current chat room
+    pub id: MessageId,
+}
+
+impl FetchThreadRequest {
+    pub fn new(id: MessageId) -> Self {
+        Self { id }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReactRequest {
+    /// Message in the current chat room
+    pub message_id: MessageId,
+    pub emoji: String,
+}
+
+impl ReactRequest {
+    pub fn new(message_id: MessageId, emoji: String) -> Self {
+        Self { message_id, emoji }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CredentialsRequest {
+    pub name: String,
+    pub password: String,
+}
+
+impl CredentialsRequest {
+    pub fn new(name: String, password: String) -> Self {
+        Self { name, password }
+    }
+}
+
+/// X25519 public key of a client, used to encrypt direct messages to it
+pub type PublicKeyBytes = [u8; 32];
+
+/// Content of a direct message encrypted for its recipient, the server cannot read it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SealedContent {
+    /// Public key of the sender, the recipient derives the shared key from it
+    pub sender_key: PublicKeyBytes,
+    pub nonce: [u8; 12],
+    /// ChaCha20-Poly1305 encrypted content
+    pub ciphertext: Vec<u8>,
+}
+
+impl SealedContent {
+    pub fn new(sender_key: PublicKeyBytes, nonce: [u8; 12], ciphertext: Vec<u8>) -> Self {
+        Self {
+            sender_key,
+            nonce,
+            ciphertext,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DirectMessageRequest {
+    /// Name of the receiving user, queued if it is a registered identity that is offline
+    pub to: String,
+    /// Empty if the content is sealed
+    pub content: String,
+    /// Content encrypted for the receiving user
+    pub sealed: Option<SealedContent>,
+}
+
+impl DirectMessageRequest {
+    pub fn new(to: String, content: String) -> Self {
+        Self {
+            to,
+            content,
+            sealed: None,
+        }
+    }
+
+    pub fn sealed(to: String, sealed: SealedContent) -> Self {
+        Self {
+            to,
+            content: String::new(),
+            sealed: Some(sealed),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PublishKeyRequest {
+    pub public_key: PublicKeyBytes,
+}
+
+impl PublishKeyRequest {
+    pub fn new(public_key: PublicKeyBytes) -> Self {
+        Self { public_key }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchKeyRequest {
+    /// Registered identity whose public key is requested
+    pub user: String,
+}
+
+impl FetchKeyRequest {
+    pub fn new(user: String) -> Self {
+        Self { user }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IgnoreRequest {
+    /// Name of the user whose messages are not delivered
+    pub user: String,
+}
+
+impl IgnoreRequest {
+    pub fn new(user: String) -> Self {
+        Self { user }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MarkReadRequest {
+    /// Chat room to mark as read up to its latest message
+    pub room: String,
+}
+
+impl MarkReadRequest {
+    pub fn new(room: String) -> Self {
+        Self { room }
+    }
+}
+
+/// Whether a user is available, offline is only reported by the server
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away,
+    DoNotDisturb,
+    Offline,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SetPresenceRequest {
+    pub presence: Presence,
+    /// Shown next to the presence, e.g. why the user is away
+    pub status: Option<String>,
+}
+
+impl SetPresenceRequest {
+    pub fn new(presence: Presence, status: Option<String>) -> Self {
+        Self { presence, status }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WhoRequest {
+    /// Chat room to list the members of
+    pub room: String,
+}
+
+impl WhoRequest {
+    pub fn new(room: String) -> Self {
+        Self { room }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchRequest {
+    pub room: String,
+    /// Words the messages have to contain, also as the start of longer words, empty to only
+    /// search by author and time
+    pub query: String,
+    /// Only messages sent under this name
+    pub author: Option<String>,
+    /// Only messages sent at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only messages sent before this time
+    pub until: Option<DateTime<Utc>>,
+    /// Only messages older than this one, to fetch the next page
+    pub before_id: Option<MessageId>,
+    /// Maximum number of results, capped by the server
+    pub limit: usize,
+}
+
+impl SearchRequest {
+    pub fn new(room: String, query: String) -> Self {
+        Self {
+            room,
+            query,
+            author: None,
+            since: None,
+            until: None,
+            before_id: None,
+            limit: 20,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FetchHistoryRequest {
+    pub room: String,
+    /// Only messages older than this one, the latest messages if not given
+    pub before_id: Option<MessageId>,
+    /// Maximum number of messages, capped by the server
+    pub limit: usize,
+}
+
+impl FetchHistoryRequest {
+    pub fn new(room: String, before_id: Option<MessageId>, limit: usize) -> Self {
+        Self {
+            room,
+            before_id,
+            limit,
+        }
+    }
+}
+
+/// Chosen by the client to tell its concurrent uploads apart
+pub type UploadId = u64;
+
+/// Hex encoded SHA-256 checksum of the content of an attachment
+pub type AttachmentId = String;
+
+/// Maximum number of bytes in a chunk of an upload or download
+pub const CHUNK_SIZE: usize = 32 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StartUploadRequest {
+    pub upload_id: UploadId,
+    pub file_name: String,
+    /// Total size of the file in bytes
+    pub size: u64,
+    /// Hex encoded SHA-256 checksum of the whole file, checked when the upload finishes
+    pub checksum: String,
+}
+
+impl StartUploadRequest {
+    pub fn new(upload_id: UploadId, file_name: String, size: u64, checksum: String) -> Self {
+        Self {
+            upload_id,
+            file_name,
+            size,
+            checksum,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadChunkRequest {
+    pub upload_id: UploadId,
+    /// Position of the chunk in the file, chunks are sent in order
+    pub offset: u64,
+    /// At most [`CHUNK_SIZE`] bytes
+    pub data: Vec<u8>,
+}
+
+impl UploadChunkRequest {
+    pub fn new(upload_id: UploadId, offset: u64, data: Vec<u8>) -> Self {
+        Self {
+            upload_id,
+            offset,
+            data,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FinishUploadRequest {
+    pub upload_id: UploadId,
+}
+
+impl FinishUploadRequest {
+    pub fn new(upload_id: UploadId) -> Self {
+        Self { upload_id }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DownloadRequest {
+    pub attachment_id: AttachmentId,
+}
+
+impl DownloadRequest {
+    pub fn new(attachment_id: AttachmentId) -> Self {
+        Self { attachment_id }
+    }
+}
@@ -0,0 +1,216 @@
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+use crate::server::communication::client::RequestKind;
+
+/// Kinds of errors the server reports to clients, new codes are only ever appended
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    InvalidMessage,
+    RoomNotFound,
+    RoomExists,
+    RateLimited,
+    NameNotAllowed,
+    InvalidPassword,
+    NotAdmin,
+    Banned,
+    Kicked,
+    Rejected,
+    NotRoomOwner,
+    InvalidFilter,
+    MessageBlocked,
+    MessageHeld,
+    HeldMessageNotFound,
+    EmptyInput,
+    InputTooLong,
+    InvalidCharacters,
+    FrameTooLarge,
+    MessageNotFound,
+    NameTaken,
+    InvalidCredentials,
+    UserNotFound,
+    NotLoggedIn,
+    AttachmentsDisabled,
+    AttachmentTooLarge,
+    UploadNotFound,
+    InvalidUpload,
+    ChecksumMismatch,
+    AttachmentNotFound,
+    KeyNotFound,
+    NameInUse,
+    MailboxFull,
+    StorageFull,
+    TooManyReactions,
+    InvalidRequest,
+}
+
+impl ErrorCode {
+    /// Stable numeric code
+    pub fn code(&self) -> u16 {
+        match self {
+            ErrorCode::InvalidMessage => 1,
+            ErrorCode::RoomNotFound => 2,
+            ErrorCode::RoomExists => 3,
+            ErrorCode::RateLimited => 4,
+            ErrorCode::NameNotAllowed => 5,
+            ErrorCode::InvalidPassword => 6,
+            ErrorCode::NotAdmin => 7,
+            ErrorCode::Banned => 8,
+            ErrorCode::Kicked => 9,
+            ErrorCode::Rejected => 10,
+            ErrorCode::NotRoomOwner => 11,
+            ErrorCode::InvalidFilter => 12,
+            ErrorCode::MessageBlocked => 13,
+            ErrorCode::MessageHeld => 14,
+            ErrorCode::HeldMessageNotFound => 15,
+            ErrorCode::EmptyInput => 16,
+            ErrorCode::InputTooLong => 17,
+            ErrorCode::InvalidCharacters => 18,
+            ErrorCode::FrameTooLarge => 19,
+            ErrorCode::MessageNotFound => 20,
+            ErrorCode::NameTaken => 21,
+            ErrorCode::InvalidCredentials => 22,
+            ErrorCode::UserNotFound => 23,
+            ErrorCode::NotLoggedIn => 24,
+            ErrorCode::AttachmentsDisabled => 25,
+            ErrorCode::AttachmentTooLarge => 26,
+            ErrorCode::UploadNotFound => 27,
+            ErrorCode::InvalidUpload => 28,
+            ErrorCode::ChecksumMismatch => 29,
+            ErrorCode::AttachmentNotFound => 30,
+            ErrorCode::KeyNotFound => 31,
+            ErrorCode::NameInUse => 32,
+            ErrorCode::MailboxFull => 33,
+            ErrorCode::StorageFull => 34,
+            ErrorCode::TooManyReactions => 35,
+            ErrorCode::InvalidRequest => 36,
+        }
+    }
+
+    /// Stable machine readable name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidMessage => "invalid_message",
+            ErrorCode::RoomNotFound => "room_not_found",
+            ErrorCode::RoomExists => "room_exists",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::NameNotAllowed => "name_not_allowed",
+            ErrorCode::InvalidPassword => "invalid_password",
+            ErrorCode::NotAdmin => "not_admin",
+            ErrorCode::Banned => "banned",
+            ErrorCode::Kicked => "kicked",
+            ErrorCode::Rejected => "rejected",
+            ErrorCode::NotRoomOwner => "not_room_owner",
+            ErrorCode::InvalidFilter => "invalid_filter",
+            ErrorCode::MessageBlocked => "message_blocked",
+            ErrorCode::MessageHeld => "message_held",
+            ErrorCode::HeldMessageNotFound => "held_message_not_found",
+            ErrorCode::EmptyInput => "empty_input",
+            ErrorCode::InputTooLong => "input_too_long",
+            ErrorCode::InvalidCharacters => "invalid_characters",
+            ErrorCode::FrameTooLarge => "frame_too_large",
+            ErrorCode::MessageNotFound => "message_not_found",
+            ErrorCode::NameTaken => "name_taken",
+            ErrorCode::InvalidCredentials => "invalid_credentials",
+            ErrorCode::UserNotFound => "user_not_found",
+            ErrorCode::NotLoggedIn => "not_logged_in",
+            ErrorCode::AttachmentsDisabled => "attachments_disabled",
+            ErrorCode::AttachmentTooLarge => "attachment_too_large",
+            ErrorCode::UploadNotFound => "upload_not_found",
+            ErrorCode::InvalidUpload => "invalid_upload",
+            ErrorCode::ChecksumMismatch => "checksum_mismatch",
+            ErrorCode::AttachmentNotFound => "attachment_not_found",
+            ErrorCode::KeyNotFound => "key_not_found",
+            ErrorCode::NameInUse => "name_in_use",
+            ErrorCode::MailboxFull => "mailbox_full",
+            ErrorCode::StorageFull => "storage_full",
+            ErrorCode::TooManyReactions => "too_many_reactions",
+            ErrorCode::InvalidRequest => "invalid_request",
+        }
+    }
+
+    /// Human readable description
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidMessage => "Received an invalid message",
+            ErrorCode::RoomNotFound => "Could not find room",
+            ErrorCode::RoomExists => "Room already exists",
+            ErrorCode::RateLimited => "Rate limit exceeded, slow down",
+            ErrorCode::NameNotAllowed => "This name is not allowed",
+            ErrorCode::InvalidPassword => "Invalid password",
+            ErrorCode::NotAdmin => "Only admins can do this",
+            ErrorCode::Banned => "You have been banned from the server",
+            ErrorCode::Kicked => "You were disconnected by an operator",
+            ErrorCode::Rejected => "Request was rejected by the server",
+            ErrorCode::NotRoomOwner => "Only the owner of the room can do this",
+            ErrorCode::InvalidFilter => "Invalid content filter",
+            ErrorCode::MessageBlocked => "Message contains blocked content",
+            ErrorCode::MessageHeld => "Message is held for review by a moderator",
+            ErrorCode::HeldMessageNotFound => "Could not find held message",
+            ErrorCode::EmptyInput => "Input must not be empty",
+            ErrorCode::InputTooLong => "Input is too long",
+            ErrorCode::InvalidCharacters => "Input contains control characters",
+            ErrorCode::FrameTooLarge => "Message exceeds the maximum frame size",
+            ErrorCode::MessageNotFound => "Could not find message",
+            ErrorCode::NameTaken => "This name is registered to someone else",
+            ErrorCode::InvalidCredentials => "Unknown user name or wrong password",
+            ErrorCode::UserNotFound => "Could not find user",
+            ErrorCode::NotLoggedIn => "Log in to a registered identity first",
+            ErrorCode::AttachmentsDisabled => "This server does not store attachments",
+            ErrorCode::AttachmentTooLarge => "Attachment exceeds the size limit",
+            ErrorCode::UploadNotFound => "Could not find upload",
+            ErrorCode::InvalidUpload => "Upload does not match what was announced",
+            ErrorCode::ChecksumMismatch => "Upload does not match its checksum",
+            ErrorCode::AttachmentNotFound => "Could not find attachment",
+            ErrorCode::KeyNotFound => "User has not published a key",
+            ErrorCode::NameInUse => "Someone connected uses this name",
+            ErrorCode::MailboxFull => "User has too many unread direct messages",
+            ErrorCode::StorageFull => "Server has no space left for attachments",
+            ErrorCode::TooManyReactions => "Message has too many different reactions",
+            ErrorCode::InvalidRequest => "Request does not ask for anything",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+/// Error reported by the server
+#[derive(Serialize, Deserialize, Clone, Debug, Error, Diagnostic)]
+#[error("{code}{}", .details.as_ref().map(|d| format!(": {d}")).unwrap_or_default())]
+pub struct ServerError {
+    /// What went wrong
+    pub code: ErrorCode,
+    /// Extra information, for room errors this is the name of the room
+    pub details: Option<String>,
+    /// Kind of request that caused the error, if any
+    pub request: Option<RequestKind>,
+}
+
+impl ServerError {
+    /// Creates an error without details
+    pub fn new(code: ErrorCode) -> Self {
+        Self {
+            code,
+            details: None,
+            request: None,
+        }
+    }
+
+    /// Adds details to the error
+    pub fn details(mut self, details: impl Into<String>) -> Self {
+        self.details = Some(details.into());
+        self
+    }
+
+    /// Sets the kind of request that caused the error
+    pub fn request(mut self, request: RequestKind) -> Self {
+        self.request = Some(request);
+        self
+    }
+}
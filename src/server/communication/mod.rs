@@ -1,9 +1,87 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::server::communication::{
+    client::MessageId,
+    server::{Attachment, PresenceResponse, Quote, ReactionsResponse, TypingResponse},
+};
+
 pub mod client;
+pub mod error;
 pub mod server;
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChatMessage {
     pub content: String,
     pub sender_name: String,
     pub sender_uuid: String,
+    /// Registered identity the sender was logged in as
+    pub sender_identity: Option<String>,
+    /// ID in the history of the chat room, assigned when the message is posted
+    pub id: Option<MessageId>,
+    /// Message this one replies to
+    pub reply_to: Option<Quote>,
+    /// ID of the first message of the thread
+    pub thread: Option<MessageId>,
+    /// Names of the connected users mentioned in the message
+    pub mentions: Vec<String>,
+    pub sent_at: DateTime<Utc>,
+    /// File attached to the message
+    pub attachment: Option<Attachment>,
+}
+
+impl ChatMessage {
+    /// Creates a message that is not part of a thread
+    pub fn new(sender_uuid: String, sender_name: String, content: String) -> Self {
+        Self {
+            content,
+            sender_name,
+            sender_uuid,
+            sender_identity: None,
+            id: None,
+            reply_to: None,
+            thread: None,
+            mentions: Vec::new(),
+            sent_at: Utc::now(),
+            attachment: None,
+        }
+    }
+}
+
+/// Hex encoded SHA-256 checksum, identifies the content of attachments
+pub fn checksum(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+/// Encodes bytes as lowercase hex
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Everything that is sent to the members of a chat room
+// Messages are by far the most common variant, boxing them would only add an allocation
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Broadcast {
+    /// A new chat message or notice
+    Message(ChatMessage),
+    /// The reactions to a message changed
+    Reactions(ReactionsResponse),
+    /// A member is typing, nothing of it is stored
+    Typing(TypingResponse),
+    /// A member changed their presence or disconnected
+    Presence(PresenceResponse),
+}
+
+impl Broadcast {
+    /// Registered identity of the member the broadcast originates from, if any
+    pub fn sender_identity(&self) -> Option<&str> {
+        match self {
+            Broadcast::Message(message) => message.sender_identity.as_deref(),
+            Broadcast::Reactions(_) => None,
+            Broadcast::Typing(typing) => typing.identity.as_deref(),
+            Broadcast::Presence(update) => update.identity.as_deref(),
+        }
+    }
 }
@@ -1,22 +1,159 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::server::communication::{
+    client::{
+        AttachmentId, MessageId, Presence, PublicKeyBytes, RequestId, RequestKind, SealedContent,
+    },
+    error::ServerError,
+    ChatMessage,
+};
+
+/// Wraps every message sent by the server
+#[derive(Serialize, Deserialize)]
+pub struct ServerEnvelope {
+    /// ID of the request this message replies to, if any
+    pub request_id: Option<RequestId>,
+    pub message: ServerMessage,
+}
+
+impl ServerEnvelope {
+    pub fn new(request_id: Option<RequestId>, message: ServerMessage) -> Self {
+        Self {
+            request_id,
+            message,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize)]
 pub enum ServerMessage {
     NewMessage(NewMessageRequest),
     JoinedChatRoom(JoinChatRoomResponse),
     ListChatRooms(ListChatRoomsResponse),
-    Err(String),
+    LeftChatRoom(LeftChatRoomResponse),
+    Announcement(AnnouncementResponse),
+    Motd(MotdResponse),
+    Ack(AckResponse),
+    Err(ServerError),
+    HeldMessages(HeldMessagesResponse),
+    Thread(ThreadResponse),
+    Reactions(ReactionsResponse),
+    DirectMessage(DirectMessageResponse),
+    SearchResults(SearchResultsResponse),
+    History(HistoryResponse),
+    DownloadChunk(DownloadChunkResponse),
+    Typing(TypingResponse),
+    Presence(PresenceResponse),
+    Who(WhoResponse),
+    Ignored(IgnoredResponse),
+    PublicKey(PublicKeyResponse),
 }
 
-#[derive(Serialize, Deserialize)]
+/// Excerpt of the message another one replies to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Quote {
+    pub id: MessageId,
+    pub user_name: String,
+    pub content: String,
+}
+
+impl Quote {
+    pub fn new(id: MessageId, user_name: String, content: String) -> Self {
+        Self {
+            id,
+            user_name,
+            content,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NewMessageRequest {
     pub content: String,
     pub user_name: String,
+    /// Registered identity of the sender, if they were logged in
+    pub identity: Option<String>,
+    /// ID in the history of the chat room, notices of the room have none
+    pub id: Option<MessageId>,
+    /// Message this one replies to
+    pub reply_to: Option<Quote>,
+    /// ID of the first message of the thread this message is part of
+    pub thread: Option<MessageId>,
+    /// Reactions to the message, only filled in for messages from the history
+    pub reactions: Vec<ReactionCount>,
+    /// Names of the users mentioned with `@name`
+    pub mentions: Vec<String>,
+    /// Whether the user receiving the message is mentioned
+    pub mentioned: bool,
+    pub sent_at: DateTime<Utc>,
+    /// File attached to the message
+    pub attachment: Option<Attachment>,
 }
 
 impl NewMessageRequest {
     pub fn new(content: String, user_name: String) -> Self {
-        Self { content, user_name }
+        Self {
+            content,
+            user_name,
+            identity: None,
+            id: None,
+            reply_to: None,
+            thread: None,
+            reactions: Vec::new(),
+            mentions: Vec::new(),
+            mentioned: false,
+            sent_at: Utc::now(),
+            attachment: None,
+        }
+    }
+}
+
+impl From<ChatMessage> for NewMessageRequest {
+    fn from(msg: ChatMessage) -> Self {
+        Self {
+            content: msg.content,
+            user_name: msg.sender_name,
+            identity: msg.sender_identity,
+            id: msg.id,
+            reply_to: msg.reply_to,
+            thread: msg.thread,
+            reactions: Vec::new(),
+            mentions: msg.mentions,
+            mentioned: false,
+            sent_at: msg.sent_at,
+            attachment: msg.attachment,
+        }
+    }
+}
+
+/// Number of users that reacted to a message with an emoji
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: usize,
+}
+
+impl ReactionCount {
+    pub fn new(emoji: String, count: usize) -> Self {
+        Self { emoji, count }
+    }
+}
+
+/// All reactions to a message after one of them changed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReactionsResponse {
+    pub message_id: MessageId,
+    pub reactions: Vec<ReactionCount>,
+}
+
+impl ReactionsResponse {
+    pub fn new(message_id: MessageId, reactions: Vec<ReactionCount>) -> Self {
+        Self {
+            message_id,
+            reactions,
+        }
     }
 }
 
@@ -34,10 +171,312 @@ impl JoinChatRoomResponse {
 #[derive(Serialize, Deserialize)]
 pub struct ListChatRoomsResponse {
     pub names: Vec<String>,
+    /// Number of unread messages per chat room, only for logged in users
+    pub unread: BTreeMap<String, usize>,
 }
 
 impl ListChatRoomsResponse {
-    pub fn new(names: Vec<String>) -> Self {
-        Self { names }
+    pub fn new(names: Vec<String>, unread: BTreeMap<String, usize>) -> Self {
+        Self { names, unread }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LeftChatRoomResponse {
+    pub name: String,
+}
+
+impl LeftChatRoomResponse {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AnnouncementResponse {
+    pub content: String,
+}
+
+impl AnnouncementResponse {
+    pub fn new(content: String) -> Self {
+        Self { content }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MotdResponse {
+    pub content: String,
+}
+
+impl MotdResponse {
+    pub fn new(content: String) -> Self {
+        Self { content }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AckResponse {
+    pub request: RequestKind,
+    /// ID of the chat message that was sent, if the request sent one to a chat room
+    pub message_id: Option<MessageId>,
+}
+
+impl AckResponse {
+    pub fn new(request: RequestKind) -> Self {
+        Self {
+            request,
+            message_id: None,
+        }
+    }
+
+    pub fn sent(request: RequestKind, message_id: MessageId) -> Self {
+        Self {
+            request,
+            message_id: Some(message_id),
+        }
+    }
+}
+
+/// Message kept back by the filter of a chat room
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeldMessage {
+    pub id: u64,
+    pub user_name: String,
+    pub content: String,
+}
+
+impl HeldMessage {
+    pub fn new(id: u64, user_name: String, content: String) -> Self {
+        Self {
+            id,
+            user_name,
+            content,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct HeldMessagesResponse {
+    pub room: String,
+    pub messages: Vec<HeldMessage>,
+}
+
+impl HeldMessagesResponse {
+    pub fn new(room: String, messages: Vec<HeldMessage>) -> Self {
+        Self { room, messages }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ThreadResponse {
+    pub room: String,
+    /// Messages of the thread from old to new, starting with the first one
+    pub messages: Vec<NewMessageRequest>,
+}
+
+impl ThreadResponse {
+    pub fn new(room: String, messages: Vec<NewMessageRequest>) -> Self {
+        Self { room, messages }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DirectMessageResponse {
+    pub from: String,
+    /// Registered identity of the sender, if they were logged in
+    pub from_identity: Option<String>,
+    /// Empty if the content is sealed
+    pub content: String,
+    /// Content encrypted for the receiving user
+    pub sealed: Option<SealedContent>,
+    pub sent_at: DateTime<Utc>,
+    /// Whether the message was queued while the user was offline
+    pub queued: bool,
+}
+
+impl DirectMessageResponse {
+    pub fn new(
+        from: String,
+        from_identity: Option<String>,
+        content: String,
+        sealed: Option<SealedContent>,
+    ) -> Self {
+        Self {
+            from,
+            from_identity,
+            content,
+            sealed,
+            sent_at: Utc::now(),
+            queued: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchResultsResponse {
+    pub room: String,
+    pub query: String,
+    /// Matching messages, newest first
+    pub messages: Vec<NewMessageRequest>,
+    /// Pass as `before_id` to fetch the next page, none if this was the last one
+    pub next_before_id: Option<MessageId>,
+}
+
+impl SearchResultsResponse {
+    pub fn new(
+        room: String,
+        query: String,
+        messages: Vec<NewMessageRequest>,
+        next_before_id: Option<MessageId>,
+    ) -> Self {
+        Self {
+            room,
+            query,
+            messages,
+            next_before_id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryResponse {
+    pub room: String,
+    /// Page of the history, oldest first
+    pub messages: Vec<NewMessageRequest>,
+    /// Pass as `before_id` to fetch the page before this one, none if this was the first one
+    pub next_before_id: Option<MessageId>,
+}
+
+impl HistoryResponse {
+    pub fn new(
+        room: String,
+        messages: Vec<NewMessageRequest>,
+        next_before_id: Option<MessageId>,
+    ) -> Self {
+        Self {
+            room,
+            messages,
+            next_before_id,
+        }
+    }
+}
+
+/// File attached to a message, its content is fetched with a download request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Attachment {
+    pub id: AttachmentId,
+    pub file_name: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+impl Attachment {
+    pub fn new(id: AttachmentId, file_name: String, size: u64) -> Self {
+        Self {
+            id,
+            file_name,
+            size,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DownloadChunkResponse {
+    pub attachment_id: AttachmentId,
+    /// Position of the chunk in the file, chunks arrive in order
+    pub offset: u64,
+    /// Total size of the file in bytes, the download is complete once it is reached
+    pub size: u64,
+    pub data: Vec<u8>,
+}
+
+impl DownloadChunkResponse {
+    pub fn new(attachment_id: AttachmentId, offset: u64, size: u64, data: Vec<u8>) -> Self {
+        Self {
+            attachment_id,
+            offset,
+            size,
+            data,
+        }
+    }
+}
+
+/// Another member of the chat room is typing, until a message of theirs or a few seconds pass
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TypingResponse {
+    pub user_name: String,
+    /// Registered identity of the user, if they are logged in
+    pub identity: Option<String>,
+}
+
+impl TypingResponse {
+    pub fn new(user_name: String, identity: Option<String>) -> Self {
+        Self {
+            user_name,
+            identity,
+        }
+    }
+}
+
+/// Presence of a user, sent to the chat room whenever it changes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PresenceResponse {
+    pub user_name: String,
+    /// Registered identity of the user, if they are logged in
+    pub identity: Option<String>,
+    pub presence: Presence,
+    pub status: Option<String>,
+}
+
+impl PresenceResponse {
+    pub fn new(
+        user_name: String,
+        identity: Option<String>,
+        presence: Presence,
+        status: Option<String>,
+    ) -> Self {
+        Self {
+            user_name,
+            identity,
+            presence,
+            status,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WhoResponse {
+    pub room: String,
+    /// Members of the chat room by name
+    pub users: Vec<PresenceResponse>,
+}
+
+impl WhoResponse {
+    pub fn new(room: String, users: Vec<PresenceResponse>) -> Self {
+        Self { room, users }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IgnoredResponse {
+    /// Identities of the ignored users, sorted
+    pub users: Vec<String>,
+}
+
+impl IgnoredResponse {
+    pub fn new(users: Vec<String>) -> Self {
+        Self { users }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PublicKeyResponse {
+    pub user: String,
+    pub public_key: PublicKeyBytes,
+}
+
+impl PublicKeyResponse {
+    pub fn new(user: String, public_key: PublicKeyBytes) -> Self {
+        Self { user, public_key }
     }
 }
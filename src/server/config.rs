@@ -0,0 +1,313 @@
+use miette::{IntoDiagnostic, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Limits how many messages a single client may send in a time window
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimit {
+    /// Maximum number of messages per window
+    pub messages: u32,
+    /// Length of the window in seconds
+    pub per_secs: u64,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            messages: 10,
+            per_secs: 5,
+        }
+    }
+}
+
+impl RateLimit {
+    /// Length of the rate limit window
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.per_secs)
+    }
+}
+
+/// Maximum sizes of client input
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Maximum size of a websocket frame or message in bytes, applies to new connections
+    pub max_frame_size: usize,
+    /// Maximum number of characters in a chat message
+    pub max_content_len: usize,
+    /// Maximum number of characters in a user name
+    pub max_name_len: usize,
+    /// Maximum number of characters in a chat room name
+    pub max_room_len: usize,
+    /// Maximum number of messages returned per page of search results or history
+    pub max_page_len: usize,
+    /// Maximum size of an attachment in bytes
+    pub max_attachment_size: u64,
+    /// Maximum size of all stored attachments together in bytes
+    pub max_blob_storage: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 64 * 1024,
+            max_content_len: 2000,
+            max_name_len: 32,
+            max_room_len: 32,
+            max_page_len: 50,
+            max_attachment_size: 10 * 1024 * 1024,
+            max_blob_storage: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Server settings, loaded from a TOML file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Message of the day, sent to every new connection
+    pub motd: Option<String>,
+    /// Password that grants admin rights to a client
+    pub admin_password: Option<String>,
+    /// Chat rooms that always exist
+    pub default_rooms: Vec<String>,
+    /// Rate limit for chat messages per client
+    pub rate_limit: RateLimit,
+    /// User names that are not allowed on the server
+    pub banned_names: Vec<String>,
+    /// Addresses that are not allowed to connect
+    pub banned_addrs: Vec<IpAddr>,
+    /// Capacity of the broadcast channel of a chat room
+    pub room_capacity: usize,
+    /// Number of messages a chat room keeps in its history, none are kept if it is 0
+    pub history_len: usize,
+    /// Maximum sizes of client input
+    pub limits: Limits,
+    /// Directory registered identities and attachments are saved in, identities are kept in
+    /// memory and attachments are disabled if not given
+    pub data_dir: Option<PathBuf>,
+    /// Secret all instances of a cluster share, required to link instances, peers presenting
+    /// another one are refused
+    pub cluster_secret: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            motd: None,
+            admin_password: None,
+            default_rooms: Vec::new(),
+            rate_limit: RateLimit::default(),
+            banned_names: Vec::new(),
+            banned_addrs: Vec::new(),
+            room_capacity: 10,
+            history_len: 1000,
+            limits: Limits::default(),
+            data_dir: None,
+            cluster_secret: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads a config from a TOML file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not read config file {}", path.display()))?;
+
+        toml::from_str(&contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Could not parse config file {}", path.display()))
+    }
+
+    /// Checks whether a user name is banned
+    pub fn is_banned_name(&self, name: &str) -> bool {
+        self.banned_names.iter().any(|banned| banned == name)
+    }
+
+    /// Checks whether a password grants admin rights
+    pub fn is_admin_password(&self, password: &str) -> bool {
+        self.admin_password.as_deref() == Some(password)
+    }
+
+    /// Checks whether an address is banned
+    pub fn is_banned_addr(&self, addr: &IpAddr) -> bool {
+        self.banned_addrs.contains(addr)
+    }
+
+    /// Compares this config with a newer one and reports what changed
+    pub fn diff(&self, new: &Config) -> ReloadReport {
+        let mut report = ReloadReport::default();
+
+        let mut check = |name: &'static str, changed: bool, live: bool| {
+            if changed && live {
+                report.applied.push(name);
+            } else if changed {
+                report.requires_restart.push(name);
+            }
+        };
+
+        check("motd", self.motd != new.motd, true);
+        check(
+            "admin_password",
+            self.admin_password != new.admin_password,
+            true,
+        );
+        // New default rooms are created at once, removed ones only disappear after a restart
+        let added = new
+            .default_rooms
+            .iter()
+            .any(|room| !self.default_rooms.contains(room));
+        let removed = self
+            .default_rooms
+            .iter()
+            .any(|room| !new.default_rooms.contains(room));
+        check("default_rooms", added, true);
+        check("default_rooms", removed, false);
+        check("rate_limit", self.rate_limit != new.rate_limit, true);
+        check("banned_names", self.banned_names != new.banned_names, true);
+        check("banned_addrs", self.banned_addrs != new.banned_addrs, true);
+        check(
+            "room_capacity",
+            self.room_capacity != new.room_capacity,
+            false,
+        );
+        check("history_len", self.history_len != new.history_len, false);
+        // The frame size is fixed when a connection is accepted, the other limits apply at once
+        let limits = Limits {
+            max_frame_size: new.limits.max_frame_size,
+            ..self.limits.clone()
+        };
+        check("limits", limits != new.limits, true);
+        check("data_dir", self.data_dir != new.data_dir, false);
+        check(
+            "cluster_secret",
+            self.cluster_secret != new.cluster_secret,
+            true,
+        );
+
+        if self.limits.max_frame_size != new.limits.max_frame_size {
+            report.requires_reconnect.push("limits.max_frame_size");
+        }
+
+        report
+    }
+}
+
+/// Outcome of reloading the configuration
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    /// Settings that were applied to the running server
+    pub applied: Vec<&'static str>,
+    /// Settings that only take effect after a restart
+    pub requires_restart: Vec<&'static str>,
+    /// Settings that only take effect for clients that connect after the reload
+    pub requires_reconnect: Vec<&'static str>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reports the changes of a config that was changed by `change` compared to the default
+    fn diff(change: impl FnOnce(&mut Config)) -> ReloadReport {
+        let mut new = Config::default();
+        change(&mut new);
+        Config::default().diff(&new)
+    }
+
+    fn assert_applied(report: ReloadReport, name: &str) {
+        assert_eq!(report.applied, [name]);
+        assert!(report.requires_restart.is_empty());
+        assert!(report.requires_reconnect.is_empty());
+    }
+
+    fn assert_requires_restart(report: ReloadReport, name: &str) {
+        assert!(report.applied.is_empty());
+        assert_eq!(report.requires_restart, [name]);
+        assert!(report.requires_reconnect.is_empty());
+    }
+
+    #[test]
+    fn unchanged_config_reports_nothing() {
+        let report = diff(|_| {});
+        assert!(report.applied.is_empty());
+        assert!(report.requires_restart.is_empty());
+        assert!(report.requires_reconnect.is_empty());
+    }
+
+    #[test]
+    fn live_settings_are_applied() {
+        assert_applied(diff(|c| c.motd = Some("hi".into())), "motd");
+        assert_applied(
+            diff(|c| c.admin_password = Some("secret".into())),
+            "admin_password",
+        );
+        assert_applied(diff(|c| c.rate_limit.messages = 1), "rate_limit");
+        assert_applied(
+            diff(|c| c.banned_names = vec!["eve".into()]),
+            "banned_names",
+        );
+        assert_applied(
+            diff(|c| c.banned_addrs = vec![[127, 0, 0, 1].into()]),
+            "banned_addrs",
+        );
+        assert_applied(diff(|c| c.limits.max_content_len = 10), "limits");
+        assert_applied(
+            diff(|c| c.cluster_secret = Some("secret".into())),
+            "cluster_secret",
+        );
+    }
+
+    #[test]
+    fn fixed_settings_require_restart() {
+        assert_requires_restart(diff(|c| c.room_capacity = 1), "room_capacity");
+        assert_requires_restart(diff(|c| c.history_len = 0), "history_len");
+        assert_requires_restart(diff(|c| c.data_dir = Some("data".into())), "data_dir");
+    }
+
+    #[test]
+    fn frame_size_requires_reconnect() {
+        let report = diff(|c| c.limits.max_frame_size = 1024);
+        assert!(report.applied.is_empty());
+        assert!(report.requires_restart.is_empty());
+        assert_eq!(report.requires_reconnect, ["limits.max_frame_size"]);
+
+        let report = diff(|c| {
+            c.limits.max_frame_size = 1024;
+            c.limits.max_name_len = 8;
+        });
+        assert_eq!(report.applied, ["limits"]);
+        assert_eq!(report.requires_reconnect, ["limits.max_frame_size"]);
+    }
+
+    #[test]
+    fn default_rooms_are_added_live_and_removed_on_restart() {
+        let old = Config {
+            default_rooms: vec!["a".into(), "b".into()],
+            ..Config::default()
+        };
+        let with_rooms = |rooms: &[&str]| Config {
+            default_rooms: rooms.iter().map(|room| room.to_string()).collect(),
+            ..Config::default()
+        };
+
+        assert_applied(old.diff(&with_rooms(&["a", "b", "c"])), "default_rooms");
+        assert_requires_restart(old.diff(&with_rooms(&["a"])), "default_rooms");
+
+        let report = old.diff(&with_rooms(&["a", "c"]));
+        assert_eq!(report.applied, ["default_rooms"]);
+        assert_eq!(report.requires_restart, ["default_rooms"]);
+
+        let report = old.diff(&with_rooms(&["b", "a"]));
+        assert!(report.applied.is_empty());
+        assert!(report.requires_restart.is_empty());
+    }
+}
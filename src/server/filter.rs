@@ -0,0 +1,140 @@
+use regex::{Regex, RegexBuilder};
+
+use crate::server::communication::{
+    client::{FilterMode, FilterSettings},
+    error::{ErrorCode, ServerError},
+};
+
+/// Maximum size of a compiled pattern in bytes, so a short pattern like `\w{1000}` cannot
+/// take up a lot of memory
+const MAX_PATTERN_SIZE: usize = 1 << 18;
+
+/// Compiled content filter of a chat room
+#[derive(Debug)]
+pub struct ContentFilter {
+    /// What happens to matching messages
+    mode: FilterMode,
+    /// Blocked words and patterns
+    patterns: Vec<Regex>,
+}
+
+impl ContentFilter {
+    /// Compiles the settings of a filter, fails on invalid regular expressions
+    pub fn new(settings: &FilterSettings) -> Result<Self, ServerError> {
+        let words = settings
+            .words
+            .iter()
+            .map(|word| format!(r"(?i)\b{}\b", regex::escape(word)));
+
+        let patterns = words
+            .chain(settings.patterns.iter().cloned())
+            .map(|pattern| {
+                RegexBuilder::new(&pattern)
+                    .size_limit(MAX_PATTERN_SIZE)
+                    .build()
+                    .map_err(|err| {
+                        ServerError::new(ErrorCode::InvalidFilter).details(err.to_string())
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            mode: settings.mode,
+            patterns,
+        })
+    }
+
+    /// What happens to matching messages
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    /// Checks whether the content contains blocked text
+    pub fn is_match(&self, content: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| pattern.is_match(content))
+    }
+
+    /// Replaces all blocked text with asterisks
+    pub fn mask(&self, content: &str) -> String {
+        self.patterns
+            .iter()
+            .fold(content.to_string(), |content, pattern| {
+                pattern
+                    .replace_all(&content, |caps: &regex::Captures| {
+                        "*".repeat(caps[0].chars().count())
+                    })
+                    .into_owned()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(words: &[&str], patterns: &[&str]) -> ContentFilter {
+        let settings = FilterSettings::new(
+            FilterMode::Mask,
+            words.iter().map(|word| word.to_string()).collect(),
+            patterns.iter().map(|pattern| pattern.to_string()).collect(),
+        );
+        ContentFilter::new(&settings).unwrap()
+    }
+
+    #[test]
+    fn words_match_whole_words_in_any_case() {
+        let filter = filter(&["darn"], &[]);
+        assert!(filter.is_match("darn"));
+        assert!(filter.is_match("Well, DARN it"));
+        assert!(!filter.is_match("darning socks"));
+        assert!(!filter.is_match("undarn"));
+        assert!(!filter.is_match(""));
+    }
+
+    #[test]
+    fn words_are_not_patterns() {
+        let filter = filter(&["a.c"], &[]);
+        assert!(filter.is_match("a.c"));
+        assert!(!filter.is_match("abc"));
+    }
+
+    #[test]
+    fn patterns_match_anywhere() {
+        let filter = filter(&[], &[r"\d{4}-\d{4}"]);
+        assert!(filter.is_match("call 1234-5678 now"));
+        assert!(!filter.is_match("call 123-5678 now"));
+    }
+
+    #[test]
+    fn empty_filter_matches_nothing() {
+        let filter = filter(&[], &[]);
+        assert!(!filter.is_match("anything"));
+        assert_eq!(filter.mask("anything"), "anything");
+    }
+
+    #[test]
+    fn mask_replaces_each_character() {
+        let filter = filter(&["grüße"], &["x+"]);
+        assert_eq!(filter.mask("Grüße and xxx!"), "***** and ***!");
+    }
+
+    #[test]
+    fn invalid_patterns_are_refused() {
+        let settings = FilterSettings::new(FilterMode::Reject, Vec::new(), vec!["(".to_string()]);
+        let error = ContentFilter::new(&settings).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidFilter);
+    }
+
+    #[test]
+    fn oversized_patterns_are_refused() {
+        let settings = FilterSettings::new(
+            FilterMode::Reject,
+            Vec::new(),
+            vec![r"\w{1000}".to_string()],
+        );
+        let error = ContentFilter::new(&settings).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidFilter);
+    }
+}
@@ -0,0 +1,106 @@
+use std::{collections::BTreeMap, net::SocketAddr, sync::Arc};
+use uuid::Uuid;
+
+use crate::server::communication::{client::ClientMessage, error::ServerError};
+
+/// Notes hooks attach to a request, logged with the request and passed to the after hooks
+pub type Annotations = BTreeMap<String, String>;
+
+/// Client a message or room event comes from
+#[derive(Debug)]
+pub struct Session<'a> {
+    /// ID of the connection
+    pub uuid: Uuid,
+    /// Address of the client
+    pub addr: SocketAddr,
+    /// User name
+    pub name: &'a str,
+    /// Chat room the client is in
+    pub room: Option<&'a str>,
+    /// Whether the client logged in as admin
+    pub is_admin: bool,
+}
+
+/// Change in the chat rooms caused by a client
+#[derive(Debug, Clone, Copy)]
+pub enum RoomEvent<'a> {
+    /// The client created a chat room
+    Created(&'a str),
+    /// The client joined a chat room
+    Joined(&'a str),
+    /// The client left a chat room, by joining another one, disconnecting or room deletion
+    Left(&'a str),
+}
+
+/// Extension point for the message processing of the server
+///
+/// Hooks are called synchronously from the connection handlers, slow work should be spawned.
+pub trait Hook: Send + Sync {
+    /// Called before a message is handled, may rewrite or annotate it, or reject it with an error
+    fn before(
+        &self,
+        _session: &Session,
+        _message: &mut ClientMessage,
+        _annotations: &mut Annotations,
+    ) -> Result<(), ServerError> {
+        Ok(())
+    }
+
+    /// Called after a message was handled, with the error reported to the client if any
+    fn after(
+        &self,
+        _session: &Session,
+        _message: &ClientMessage,
+        _annotations: &Annotations,
+        _outcome: Result<(), &ServerError>,
+    ) {
+    }
+
+    /// Called when a client creates, joins or leaves a chat room
+    fn room_event(&self, _session: &Session, _event: RoomEvent) {}
+}
+
+/// Hooks the server is configured with, called in the order they were added
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Vec<Arc<dyn Hook>>,
+}
+
+impl Hooks {
+    /// Adds a hook after the existing ones
+    pub fn push(&mut self, hook: impl Hook + 'static) {
+        self.hooks.push(Arc::new(hook));
+    }
+
+    /// Runs the before hooks, stops at the first one that rejects the message
+    pub fn before(
+        &self,
+        session: &Session,
+        message: &mut ClientMessage,
+        annotations: &mut Annotations,
+    ) -> Result<(), ServerError> {
+        self.hooks
+            .iter()
+            .try_for_each(|hook| hook.before(session, message, annotations))
+    }
+
+    /// Runs the after hooks
+    pub fn after(
+        &self,
+        session: &Session,
+        message: &ClientMessage,
+        annotations: &Annotations,
+        outcome: Result<(), &ServerError>,
+    ) {
+        for hook in &self.hooks {
+            hook.after(session, message, annotations, outcome);
+        }
+    }
+
+    /// Runs the room event hooks
+    pub fn room_event(&self, session: &Session, event: RoomEvent) {
+        for hook in &self.hooks {
+            hook.room_event(session, event);
+        }
+    }
+}
@@ -0,0 +1,717 @@
+use futures_channel::mpsc;
+use futures_util::StreamExt;
+use miette::{IntoDiagnostic, Result};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::poll_fn,
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+use crate::server::communication::{
+    client::{
+        ChangeNameRequest, ClientEnvelope, ClientMakeChatRoomRequest, ClientMessage,
+        DirectMessageRequest, JoinChatRoomRequest, Presence, RequestId, SendMessageRequest,
+        WhoRequest,
+    },
+    error::{ErrorCode, ServerError},
+    server::{ServerEnvelope, ServerMessage},
+};
+
+/// Name the gateway uses as the source of its own replies
+const SERVER_NAME: &str = "chat-server";
+
+/// Number of messages buffered between a gateway and the handler of its client
+pub const CHANNEL_CAPACITY: usize = 16;
+
+/// Number of queued requests at which the gateway stops reading lines from its client
+const MAX_QUEUED_REQUESTS: usize = 64;
+
+/// Request the gateway sent on behalf of its client, to translate the reply
+enum Pending {
+    /// Choosing the nickname while registering
+    Register(String),
+    /// Changing the nickname after registering
+    Nick(String),
+    /// Creating the room of a channel before joining it, errors are left to the join
+    MakeRoom,
+    /// Joining a channel
+    Join(String),
+    /// Leaving the current channel
+    Part,
+    /// Sending to a channel or user
+    Message(String),
+    /// Counting the members of a channel for the channel list, the last one ends the list
+    List { last: bool },
+    /// Listing the members of a channel
+    Names(String),
+}
+
+/// Splits an IRC line into its uppercase command and its parameters, tags and the source
+/// sent by the client are ignored
+fn parse(line: &str) -> Option<(String, Vec<String>)> {
+    let mut rest = line.trim_start();
+    if rest.starts_with('@') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+
+    let (middle, trailing) = match rest.split_once(" :") {
+        Some((middle, trailing)) => (middle, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut words = middle.split(' ').filter(|word| !word.is_empty());
+    let command = words.next()?.to_uppercase();
+    let mut params: Vec<String> = words.map(str::to_string).collect();
+    params.extend(trailing.map(str::to_string));
+
+    Some((command, params))
+}
+
+/// Chat room of an IRC channel name
+fn room_of(channel: &str) -> Option<&str> {
+    channel.strip_prefix('#').filter(|room| !room.is_empty())
+}
+
+/// Only rooms without spaces and commas can be written as IRC channels
+fn is_channel_name(room: &str) -> bool {
+    !room.is_empty() && !room.contains([' ', ',', '\x07'])
+}
+
+/// IRC nicknames cannot contain spaces, which user names may
+fn nick_of(name: &str) -> String {
+    name.replace(' ', "_")
+}
+
+/// Source of a message a user sent
+fn source(name: &str) -> String {
+    let nick = nick_of(name);
+    format!("{nick}!{nick}@{SERVER_NAME}")
+}
+
+/// Splits text into lines that can each be sent in one IRC message, a carriage return would
+/// end a line early just like a line feed
+fn lines_of(text: &str) -> impl Iterator<Item = &str> {
+    text.split(['\r', '\n']).filter(|line| !line.is_empty())
+}
+
+/// Turns CTCP actions into plain text, other CTCP requests have no equivalent
+fn content_of(text: &str) -> Option<String> {
+    match text.strip_prefix('\x01') {
+        Some(ctcp) => ctcp
+            .trim_end_matches('\x01')
+            .strip_prefix("ACTION ")
+            .map(|action| format!("* {action}")),
+        None => Some(text.to_string()),
+    }
+}
+
+/// Translates between an IRC client and the handler that serves it like any other client
+struct Gateway {
+    /// Sending half of the connection to the IRC client
+    writer: OwnedWriteHalf,
+    /// Client messages the IRC commands translate to, waiting for room in the channel to the
+    /// handler
+    outbox: VecDeque<Message>,
+    /// Nickname of the client, `*` until it registered
+    nick: String,
+    /// Nickname the client asked for before it sent the USER command
+    wanted_nick: Option<String>,
+    /// Whether the client sent the USER command
+    has_user: bool,
+    /// Whether the client completed the registration
+    registered: bool,
+    /// Room of the channel the client is in
+    channel: Option<String>,
+    /// Message of the day, shown after the registration
+    motd: Option<String>,
+    /// ID of the next request to the handler
+    next_request: RequestId,
+    /// Requests waiting for their reply
+    pending: HashMap<RequestId, Pending>,
+}
+
+impl Gateway {
+    /// Sends a line to the IRC client
+    async fn send_line(&mut self, line: String) -> Result<()> {
+        self.writer
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await
+            .into_diagnostic()
+    }
+
+    /// Sends a numeric reply to the IRC client
+    async fn numeric(&mut self, code: &str, params: &str) -> Result<()> {
+        let line = format!(":{SERVER_NAME} {code} {} {params}", self.nick);
+        self.send_line(line).await
+    }
+
+    /// Sends a notice from the server to the IRC client
+    async fn notice(&mut self, text: &str) -> Result<()> {
+        let nick = self.nick.clone();
+        self.notice_to(&nick, text).await
+    }
+
+    /// Sends a notice from the server to a user or channel, one message per line
+    async fn notice_to(&mut self, target: &str, text: &str) -> Result<()> {
+        for line in lines_of(text) {
+            let line = format!(":{SERVER_NAME} NOTICE {target} :{line}");
+            self.send_line(line).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Queues a client message for the handler, remembers what it was for if the reply matters
+    ///
+    /// Requests are never sent right away, as the handler may be waiting for its replies to be
+    /// read at the same time
+    fn request(&mut self, message: ClientMessage, pending: Option<Pending>) -> Result<()> {
+        let request_id = self.next_request;
+        self.next_request += 1;
+        if let Some(pending) = pending {
+            self.pending.insert(request_id, pending);
+        }
+
+        let envelope = ClientEnvelope::new(Some(request_id), message);
+        let msg = Message::Binary(bincode::serialize(&envelope).into_diagnostic()?);
+        self.outbox.push_back(msg);
+
+        Ok(())
+    }
+
+    /// Picks the nickname once the client sent both NICK and USER
+    fn register(&mut self) -> Result<()> {
+        if !self.has_user {
+            return Ok(());
+        }
+
+        if let Some(nick) = self.wanted_nick.take() {
+            let message = ClientMessage::ChangeName(ChangeNameRequest::new(nick.clone()));
+            self.request(message, Some(Pending::Register(nick)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Greets the client after its nickname was accepted
+    async fn welcome(&mut self, nick: String) -> Result<()> {
+        self.nick = nick_of(&nick);
+        self.registered = true;
+
+        let welcome = format!(":Welcome to the chat, {}", self.nick);
+        self.numeric("001", &welcome).await?;
+        self.numeric("002", &format!(":Your host is {SERVER_NAME}"))
+            .await?;
+
+        match self.motd.clone() {
+            Some(motd) => {
+                self.numeric("375", &format!(":- {SERVER_NAME} Message of the day -"))
+                    .await?;
+                for line in lines_of(&motd) {
+                    self.numeric("372", &format!(":- {line}")).await?;
+                }
+                self.numeric("376", ":End of /MOTD command").await
+            }
+            None => self.numeric("422", ":MOTD File is missing").await,
+        }
+    }
+
+    /// Reports an error of a request about a channel or user
+    async fn report(&mut self, target: &str, error: ServerError) -> Result<()> {
+        match error.code {
+            ErrorCode::RoomNotFound => {
+                self.numeric("403", &format!("{target} :No such channel"))
+                    .await
+            }
+            ErrorCode::UserNotFound => {
+                self.numeric("401", &format!("{target} :No such nick"))
+                    .await
+            }
+            _ => self.notice(&error.to_string()).await,
+        }
+    }
+
+    /// Reports a nickname that was not accepted
+    async fn reject_nick(&mut self, nick: &str, error: ServerError) -> Result<()> {
+        match error.code {
+            ErrorCode::NameTaken => {
+                let reply = format!("{nick} :Nickname is registered, log in with a native client");
+                self.numeric("433", &reply).await
+            }
+            _ => self.numeric("432", &format!("{nick} :{error}")).await,
+        }
+    }
+
+    /// Handles a line from the IRC client, returns false if the client quits
+    async fn handle_line(&mut self, line: &str) -> Result<bool> {
+        let Some((command, params)) = parse(line) else {
+            return Ok(true);
+        };
+        let param = |index: usize| params.get(index).map(String::as_str);
+
+        match (command.as_str(), self.registered) {
+            ("PING", _) => {
+                let token = param(0).unwrap_or(SERVER_NAME);
+                let line = format!(":{SERVER_NAME} PONG {SERVER_NAME} :{token}");
+                self.send_line(line).await?;
+            }
+            ("QUIT", _) => {
+                self.send_line("ERROR :Closing link".to_string()).await?;
+                return Ok(false);
+            }
+            // Capabilities are not negotiated, passwords are only checked by native clients
+            ("CAP" | "PASS" | "PONG", _) => {}
+            ("NICK", registered) => match param(0) {
+                Some(nick) if registered => {
+                    let message = ClientMessage::ChangeName(ChangeNameRequest::new(nick.into()));
+                    self.request(message, Some(Pending::Nick(nick.into())))?;
+                }
+                Some(nick) => {
+                    self.wanted_nick = Some(nick.into());
+                    self.register()?;
+                }
+                None => self.numeric("431", ":No nickname given").await?,
+            },
+            ("USER", true) => self.numeric("462", ":You may not reregister").await?,
+            ("USER", false) if params.len() < 4 => {
+                self.numeric("461", "USER :Not enough parameters").await?
+            }
+            ("USER", false) => {
+                self.has_user = true;
+                self.register()?;
+            }
+            (_, false) => self.numeric("451", ":You have not registered").await?,
+            ("JOIN", true) => {
+                let Some(channels) = param(0) else {
+                    return self
+                        .numeric("461", "JOIN :Not enough parameters")
+                        .await
+                        .map(|()| true);
+                };
+
+                // Clients are in one room at a time, so joining several ends up in the last
+                for channel in channels.split(',') {
+                    if channel == "0" {
+                        self.request(ClientMessage::LeaveChatRoom(), Some(Pending::Part))?;
+                        continue;
+                    }
+
+                    let Some(room) = room_of(channel) else {
+                        self.numeric("403", &format!("{channel} :No such channel"))
+                            .await?;
+                        continue;
+                    };
+
+                    // Joining a channel that does not exist creates it
+                    let make = ClientMakeChatRoomRequest::new(room.to_string());
+                    self.request(ClientMessage::MakeChatRoom(make), Some(Pending::MakeRoom))?;
+                    let join = JoinChatRoomRequest::new(room.to_string());
+                    let pending = Pending::Join(channel.to_string());
+                    self.request(ClientMessage::JoinChatRoom(join), Some(pending))?;
+                }
+            }
+            ("PART", true) => {
+                let channel = param(0).unwrap_or_default();
+                if room_of(channel).is_none() || room_of(channel) != self.channel.as_deref() {
+                    let reply = format!("{channel} :You're not on that channel");
+                    self.numeric("442", &reply).await?;
+                } else {
+                    self.request(ClientMessage::LeaveChatRoom(), Some(Pending::Part))?;
+                }
+            }
+            ("PRIVMSG", true) => {
+                let (Some(target), Some(text)) = (param(0), param(1)) else {
+                    return self.numeric("412", ":No text to send").await.map(|()| true);
+                };
+                let Some(content) = content_of(text) else {
+                    return Ok(true);
+                };
+
+                let message = match room_of(target) {
+                    Some(room) if Some(room) == self.channel.as_deref() => {
+                        ClientMessage::SendMessage(SendMessageRequest::new(content))
+                    }
+                    Some(_) => {
+                        let reply = format!("{target} :Cannot send to channel");
+                        self.numeric("404", &reply).await?;
+                        return Ok(true);
+                    }
+                    None => ClientMessage::DirectMessage(DirectMessageRequest::new(
+                        target.to_string(),
+                        content,
+                    )),
+                };
+                self.request(message, Some(Pending::Message(target.to_string())))?;
+            }
+            ("LIST", true) => {
+                self.numeric("321", "Channel :Users  Name").await?;
+                self.request(ClientMessage::ListChatRooms(), None)?;
+            }
+            ("NAMES", true) => {
+                let room = match param(0) {
+                    Some(channel) => room_of(channel).map(str::to_string),
+                    None => self.channel.clone(),
+                };
+
+                match room {
+                    Some(room) => {
+                        let who = ClientMessage::Who(WhoRequest::new(room.clone()));
+                        self.request(who, Some(Pending::Names(room)))?;
+                    }
+                    None => self.numeric("366", "* :End of /NAMES list").await?,
+                }
+            }
+            // Chat rooms have no topic
+            ("TOPIC", true) => match (param(0), param(1)) {
+                (Some(channel), None) => {
+                    let reply = format!("{channel} :No topic is set");
+                    self.numeric("331", &reply).await?;
+                }
+                (Some(channel), Some(_)) => {
+                    let reply = format!("{channel} :Chat rooms have no topic");
+                    self.numeric("482", &reply).await?;
+                }
+                (None, _) => self.numeric("461", "TOPIC :Not enough parameters").await?,
+            },
+            (command, true) => {
+                let reply = format!("{command} :Unknown command");
+                self.numeric("421", &reply).await?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Translates a message of the handler for the IRC client
+    async fn handle_server_msg(&mut self, msg: Message) -> Result<()> {
+        let Message::Binary(bytes) = msg else {
+            return Ok(());
+        };
+        let ServerEnvelope {
+            request_id,
+            message,
+        } = bincode::deserialize(&bytes).into_diagnostic()?;
+        let pending = request_id.and_then(|id| self.pending.remove(&id));
+
+        match (message, pending) {
+            (ServerMessage::NewMessage(message), _) => {
+                let Some(room) = self.channel.clone() else {
+                    return Ok(());
+                };
+
+                // Notices have no ID, as they are not kept in the history
+                if message.id.is_none() {
+                    return self.notice_to(&format!("#{room}"), &message.content).await;
+                }
+
+                let source = source(&message.user_name);
+                for line in lines_of(&message.content) {
+                    self.send_line(format!(":{source} PRIVMSG #{room} :{line}"))
+                        .await?;
+                }
+                if let Some(attachment) = message.attachment {
+                    let line = format!(
+                        ":{source} PRIVMSG #{room} :[attachment {}, {} bytes]",
+                        attachment.file_name, attachment.size
+                    );
+                    self.send_line(line).await?;
+                }
+            }
+            (ServerMessage::DirectMessage(message), _) if message.sealed.is_some() => {
+                let text = format!(
+                    "{} sent an encrypted direct message, read it with a native client",
+                    message.from
+                );
+                self.notice(&text).await?;
+            }
+            (ServerMessage::DirectMessage(message), _) => {
+                let source = source(&message.from);
+                for line in lines_of(&message.content) {
+                    let line = format!(":{source} PRIVMSG {} :{line}", self.nick);
+                    self.send_line(line).await?;
+                }
+            }
+            (ServerMessage::Announcement(announcement), _) => {
+                self.notice(&announcement.content).await?;
+            }
+            (ServerMessage::Presence(update), _) if update.presence == Presence::Offline => {
+                let line = format!(":{} QUIT :Disconnected", source(&update.user_name));
+                self.send_line(line).await?;
+            }
+            (ServerMessage::JoinedChatRoom(joined), _) => {
+                let me = source(&self.nick);
+                if let Some(previous) = self.channel.replace(joined.name.clone()) {
+                    self.send_line(format!(":{me} PART #{previous}")).await?;
+                }
+                self.send_line(format!(":{me} JOIN #{}", joined.name))
+                    .await?;
+
+                let who = ClientMessage::Who(WhoRequest::new(joined.name.clone()));
+                self.request(who, Some(Pending::Names(joined.name)))?;
+            }
+            (ServerMessage::LeftChatRoom(left), _) => {
+                self.channel = None;
+                let line = format!(":{} PART #{}", source(&self.nick), left.name);
+                self.send_line(line).await?;
+            }
+            (ServerMessage::ListChatRooms(list), _) => {
+                let mut rooms: Vec<String> = list
+                    .names
+                    .into_iter()
+                    .filter(|room| is_channel_name(room))
+                    .collect();
+                rooms.sort();
+
+                if rooms.is_empty() {
+                    self.numeric("323", ":End of /LIST").await?;
+                }
+                let count = rooms.len();
+                for (index, room) in rooms.into_iter().enumerate() {
+                    let who = ClientMessage::Who(WhoRequest::new(room));
+                    let pending = Pending::List {
+                        last: index + 1 == count,
+                    };
+                    self.request(who, Some(pending))?;
+                }
+            }
+            (ServerMessage::Who(who), Some(Pending::List { last })) => {
+                let reply = format!("#{} {} :", who.room, who.users.len());
+                self.numeric("322", &reply).await?;
+                if last {
+                    self.numeric("323", ":End of /LIST").await?;
+                }
+            }
+            (ServerMessage::Who(who), Some(Pending::Names(room))) => {
+                let names: Vec<String> = who
+                    .users
+                    .iter()
+                    .map(|user| nick_of(&user.user_name))
+                    .collect();
+                self.numeric("353", &format!("= #{room} :{}", names.join(" ")))
+                    .await?;
+                self.numeric("366", &format!("#{room} :End of /NAMES list"))
+                    .await?;
+            }
+            (ServerMessage::Ack(_), Some(Pending::Register(nick))) if !self.registered => {
+                self.welcome(nick).await?
+            }
+            // A second nickname sent while registering changes the first one
+            (ServerMessage::Ack(_), Some(Pending::Register(nick) | Pending::Nick(nick))) => {
+                let line = format!(":{} NICK :{}", source(&self.nick), nick_of(&nick));
+                self.send_line(line).await?;
+                self.nick = nick_of(&nick);
+            }
+            (ServerMessage::Err(error), Some(pending)) => match pending {
+                Pending::Register(nick) | Pending::Nick(nick) => {
+                    self.reject_nick(&nick, error).await?
+                }
+                Pending::MakeRoom => {}
+                Pending::Join(channel) | Pending::Message(channel) => {
+                    self.report(&channel, error).await?
+                }
+                Pending::Part => self.notice(&error.to_string()).await?,
+                Pending::List { last } => {
+                    if last {
+                        self.numeric("323", ":End of /LIST").await?;
+                    }
+                }
+                Pending::Names(room) => {
+                    self.numeric("366", &format!("#{room} :End of /NAMES list"))
+                        .await?
+                }
+            },
+            (ServerMessage::Err(error), None) => match error.code {
+                ErrorCode::Kicked | ErrorCode::Banned => {
+                    let reason = lines_of(&error.to_string()).collect::<Vec<_>>().join(" ");
+                    self.send_line(format!("ERROR :{reason}")).await?
+                }
+                _ => self.notice(&error.to_string()).await?,
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a line of at most `limit` bytes, keeps what was read if it is cancelled
+async fn read_line(
+    reader: &mut (impl AsyncBufRead + Unpin),
+    line: &mut Vec<u8>,
+    limit: usize,
+) -> Result<usize> {
+    let remaining = limit.saturating_sub(line.len()) as u64;
+    reader
+        .take(remaining)
+        .read_until(b'\n', line)
+        .await
+        .into_diagnostic()
+}
+
+/// Serves an IRC client, translating its commands for the handler and the replies back
+pub async fn serve(
+    conn: TcpStream,
+    motd: Option<String>,
+    max_line_len: usize,
+    mut handler: mpsc::Sender<Result<Message, tungstenite::Error>>,
+    mut replies: mpsc::Receiver<Message>,
+) -> Result<()> {
+    let (read, writer) = conn.into_split();
+    let mut reader = BufReader::new(read);
+    let mut line = Vec::new();
+
+    let mut gateway = Gateway {
+        writer,
+        outbox: VecDeque::new(),
+        nick: "*".to_string(),
+        wanted_nick: None,
+        has_user: false,
+        registered: false,
+        channel: None,
+        motd,
+        next_request: 0,
+        pending: HashMap::new(),
+    };
+
+    loop {
+        tokio::select! {
+            read = read_line(&mut reader, &mut line, max_line_len),
+                if gateway.outbox.len() < MAX_QUEUED_REQUESTS =>
+            {
+                if read? == 0 {
+                    break;
+                }
+                if !line.ends_with(b"\n") {
+                    if line.len() >= max_line_len {
+                        gateway.send_line("ERROR :Line too long".to_string()).await?;
+                        break;
+                    }
+                    continue;
+                }
+
+                let text = String::from_utf8_lossy(&line).trim_end().to_string();
+                line.clear();
+                if !gateway.handle_line(&text).await? {
+                    break;
+                }
+            }
+            // Only waits for room, so no request is lost when another branch completes first
+            ready = poll_fn(|cx| handler.poll_ready(cx)), if !gateway.outbox.is_empty() => {
+                ready.into_diagnostic()?;
+                if let Some(msg) = gateway.outbox.pop_front() {
+                    handler.start_send(Ok(msg)).into_diagnostic()?;
+                }
+            }
+            // The handler closes the channel when it stops serving the client
+            msg = replies.next() => match msg {
+                Some(msg) => gateway.handle_server_msg(msg).await?,
+                None => break,
+            },
+        }
+    }
+
+    gateway.writer.shutdown().await.into_diagnostic()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::{io::duplex, time};
+
+    fn command(command: &str, params: &[&str]) -> Option<(String, Vec<String>)> {
+        Some((
+            command.to_string(),
+            params.iter().map(|param| param.to_string()).collect(),
+        ))
+    }
+
+    #[test]
+    fn parses_command_and_params() {
+        assert_eq!(parse("nick alice"), command("NICK", &["alice"]));
+        assert_eq!(
+            parse("PRIVMSG #lobby :hello there"),
+            command("PRIVMSG", &["#lobby", "hello there"])
+        );
+        assert_eq!(parse("QUIT"), command("QUIT", &[]));
+    }
+
+    #[test]
+    fn skips_tags_and_source() {
+        assert_eq!(
+            parse("@time=now :alice!a@host JOIN #lobby"),
+            command("JOIN", &["#lobby"])
+        );
+    }
+
+    #[test]
+    fn keeps_empty_and_colon_trailing_params() {
+        assert_eq!(parse("TOPIC #lobby :"), command("TOPIC", &["#lobby", ""]));
+        assert_eq!(
+            parse("PRIVMSG bob ::-) a :b"),
+            command("PRIVMSG", &["bob", ":-) a :b"])
+        );
+    }
+
+    #[test]
+    fn collapses_repeated_spaces() {
+        assert_eq!(parse("  JOIN   #a  #b "), command("JOIN", &["#a", "#b"]));
+    }
+
+    #[test]
+    fn empty_and_whitespace_lines_have_no_command() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   "), None);
+        assert_eq!(parse(":alice"), None);
+        assert_eq!(parse("@tags"), None);
+    }
+
+    #[test]
+    fn lines_split_on_crlf_and_lone_carriage_returns() {
+        let lines: Vec<&str> = lines_of("one\r\ntwo\rthree\n\nfour").collect();
+        assert_eq!(lines, ["one", "two", "three", "four"]);
+        assert_eq!(lines_of("").count(), 0);
+        assert_eq!(lines_of("\r\n\r\n").count(), 0);
+    }
+
+    #[tokio::test]
+    async fn partial_lines_are_kept_until_they_end() {
+        let (mut client, server) = duplex(64);
+        let mut reader = BufReader::new(server);
+        let mut line = Vec::new();
+
+        client.write_all(b"NICK al").await.unwrap();
+        let read = time::timeout(
+            Duration::from_millis(50),
+            read_line(&mut reader, &mut line, 512),
+        )
+        .await;
+        assert!(read.is_err());
+        assert_eq!(line, b"NICK al");
+
+        client.write_all(b"ice\r\nQUIT\r\n").await.unwrap();
+        read_line(&mut reader, &mut line, 512).await.unwrap();
+        assert_eq!(line, b"NICK alice\r\n");
+    }
+
+    #[tokio::test]
+    async fn long_lines_stop_at_the_limit() {
+        let (mut client, server) = duplex(64);
+        let mut reader = BufReader::new(server);
+        let mut line = b"NICK".to_vec();
+
+        client.write_all(b" alice\r\n").await.unwrap();
+        let read = read_line(&mut reader, &mut line, 8).await.unwrap();
+        assert_eq!(read, 4);
+        assert_eq!(line, b"NICK ali");
+
+        let read = read_line(&mut reader, &mut line, 8).await.unwrap();
+        assert_eq!(read, 0);
+    }
+}
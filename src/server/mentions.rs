@@ -0,0 +1,58 @@
+/// Characters that end a mention, so that `@alice,` mentions `alice`
+fn is_trailing_punctuation(c: char) -> bool {
+    matches!(c, ',' | '.' | ':' | ';' | '!' | '?' | ')' | '"' | '\'')
+}
+
+/// Finds the names mentioned with `@name` in a chat message, without duplicates
+pub fn parse(content: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for word in content.split_whitespace() {
+        let Some(name) = word.strip_prefix('@') else {
+            continue;
+        };
+        let name = name.trim_end_matches(is_trailing_punctuation);
+
+        if !name.is_empty() && !names.iter().any(|n| n == name) {
+            names.push(name.to_string());
+        }
+    }
+
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_names_after_an_at() {
+        assert_eq!(parse("hi @alice and @bob"), ["alice", "bob"]);
+        assert_eq!(parse("@alice"), ["alice"]);
+    }
+
+    #[test]
+    fn strips_trailing_punctuation() {
+        assert_eq!(
+            parse("@alice, @bob: @dave!?"),
+            ["alice", "bob", "dave"]
+        );
+        assert_eq!(parse("@o'neil's"), ["o'neil's"]);
+    }
+
+    #[test]
+    fn skips_duplicates_and_bare_ats() {
+        assert_eq!(parse("@alice @alice. @ @! mail@example.com"), ["alice"]);
+    }
+
+    #[test]
+    fn empty_and_whitespace_content_mentions_nobody() {
+        assert!(parse("").is_empty());
+        assert!(parse(" \n\t ").is_empty());
+    }
+
+    #[test]
+    fn mentions_span_lines() {
+        assert_eq!(parse("@alice\n@bob\r\n@carol"), ["alice", "bob", "carol"]);
+    }
+}
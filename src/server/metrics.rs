@@ -0,0 +1,257 @@
+use miette::{miette, IntoDiagnostic, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+    time,
+};
+use tracing::{info, warn};
+
+use crate::server::backend::Backend;
+
+/// Most chat rooms that get their own label, messages in rooms beyond it are counted without one
+const MAX_ROOM_LABELS: usize = 500;
+
+/// Time a scraper gets to send its request before the connection is dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Escapes a prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Writes the help and type header of a metric
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Instrumentation of the server, updated by the handlers
+#[derive(Default)]
+pub struct Metrics {
+    /// Clients with an open websocket
+    connected_clients: AtomicI64,
+    /// Handler tasks that were spawned
+    handlers_started: AtomicU64,
+    /// Handler tasks that stopped without an error
+    handlers_closed: AtomicU64,
+    /// Handler tasks that stopped because of an error
+    handlers_failed: AtomicU64,
+    /// Times a handler fell behind its chat room broadcast channel
+    lag_events: AtomicU64,
+    /// Messages that were dropped because a handler fell behind
+    lagged_messages: AtomicU64,
+    /// Chat messages sent per room
+    messages: Mutex<HashMap<String, u64>>,
+    /// Chat messages sent in rooms beyond the label limit
+    unlabeled_messages: AtomicU64,
+    /// Rejected client requests per kind of error
+    rejected: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    /// Records a newly spawned handler
+    pub fn handler_started(&self) {
+        self.handlers_started.fetch_add(1, Ordering::Relaxed);
+        self.connected_clients.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a handler that stopped, with or without an error
+    pub fn handler_finished(&self, failed: bool) {
+        self.connected_clients.fetch_sub(1, Ordering::Relaxed);
+
+        if failed {
+            self.handlers_failed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.handlers_closed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a handler falling behind its chat room
+    pub fn lagged(&self, skipped: u64) {
+        self.lag_events.fetch_add(1, Ordering::Relaxed);
+        self.lagged_messages.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    /// Records a chat message sent to a room
+    pub fn message_sent(&self, room: &str) {
+        let mut messages = self.messages.lock().unwrap();
+
+        if let Some(count) = messages.get_mut(room) {
+            *count += 1;
+        } else if messages.len() < MAX_ROOM_LABELS {
+            messages.insert(room.to_string(), 1);
+        } else {
+            self.unlabeled_messages.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Drops the labels of chat rooms that were deleted
+    pub fn retain_rooms(&self, rooms: &[String]) {
+        let rooms: HashSet<&String> = rooms.iter().collect();
+        let mut messages = self.messages.lock().unwrap();
+        messages.retain(|room, _| rooms.contains(room));
+    }
+
+    /// Records a rejected client request
+    pub fn rejected(&self, kind: &'static str) {
+        let mut rejected = self.rejected.lock().unwrap();
+        *rejected.entry(kind).or_default() += 1;
+    }
+
+    /// Renders all metrics in the prometheus text format
+    pub fn render(&self, rooms: usize) -> String {
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "chat_connected_clients",
+            "gauge",
+            "Clients with an open websocket",
+        );
+        let clients = self.connected_clients.load(Ordering::Relaxed);
+        let _ = writeln!(out, "chat_connected_clients {clients}");
+
+        write_header(&mut out, "chat_rooms", "gauge", "Chat rooms on the server");
+        let _ = writeln!(out, "chat_rooms {rooms}");
+
+        write_header(
+            &mut out,
+            "chat_messages_total",
+            "counter",
+            "Chat messages sent per room, rooms beyond the label limit are counted without a label",
+        );
+        for (room, count) in self.messages.lock().unwrap().iter() {
+            let room = escape_label(room);
+            let _ = writeln!(out, "chat_messages_total{{room=\"{room}\"}} {count}");
+        }
+        let unlabeled = self.unlabeled_messages.load(Ordering::Relaxed);
+        let _ = writeln!(out, "chat_messages_total {unlabeled}");
+
+        write_header(
+            &mut out,
+            "chat_broadcast_lag_events_total",
+            "counter",
+            "Times a handler fell behind its chat room",
+        );
+        let lag_events = self.lag_events.load(Ordering::Relaxed);
+        let _ = writeln!(out, "chat_broadcast_lag_events_total {lag_events}");
+
+        write_header(
+            &mut out,
+            "chat_broadcast_lagged_messages_total",
+            "counter",
+            "Messages dropped because a handler fell behind",
+        );
+        let lagged = self.lagged_messages.load(Ordering::Relaxed);
+        let _ = writeln!(out, "chat_broadcast_lagged_messages_total {lagged}");
+
+        write_header(
+            &mut out,
+            "chat_rejected_requests_total",
+            "counter",
+            "Rejected client requests per kind of error",
+        );
+        for (kind, count) in self.rejected.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "chat_rejected_requests_total{{kind=\"{kind}\"}} {count}"
+            );
+        }
+
+        write_header(
+            &mut out,
+            "chat_handler_tasks_started_total",
+            "counter",
+            "Handler tasks that were spawned",
+        );
+        let started = self.handlers_started.load(Ordering::Relaxed);
+        let _ = writeln!(out, "chat_handler_tasks_started_total {started}");
+
+        write_header(
+            &mut out,
+            "chat_handler_tasks_finished_total",
+            "counter",
+            "Handler tasks that stopped, per outcome",
+        );
+        let closed = self.handlers_closed.load(Ordering::Relaxed);
+        let failed = self.handlers_failed.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "chat_handler_tasks_finished_total{{outcome=\"closed\"}} {closed}"
+        );
+        let _ = writeln!(
+            out,
+            "chat_handler_tasks_finished_total{{outcome=\"error\"}} {failed}"
+        );
+
+        out
+    }
+}
+
+/// Answers a single HTTP request on the metrics endpoint
+async fn handle_scrape(
+    mut conn: TcpStream,
+    metrics: &Metrics,
+    backend: &RwLock<Backend>,
+) -> Result<()> {
+    let mut buffer = [0; 1024];
+    let read = time::timeout(READ_TIMEOUT, conn.read(&mut buffer))
+        .await
+        .map_err(|_| miette!("Timed out waiting for the request"))?
+        .into_diagnostic()?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+
+    let response = match request.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let rooms = backend.read().await.list();
+            metrics.retain_rooms(&rooms);
+            let body = metrics.render(rooms.len());
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    conn.write_all(response.as_bytes())
+        .await
+        .into_diagnostic()?;
+    conn.shutdown().await.into_diagnostic()
+}
+
+/// Serves the metrics over HTTP on the given address
+pub async fn serve(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    backend: Arc<RwLock<Backend>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await.into_diagnostic()?;
+    info!(%addr, "Serving metrics");
+
+    loop {
+        let (conn, peer) = listener.accept().await.into_diagnostic()?;
+        let metrics = metrics.clone();
+        let backend = backend.clone();
+
+        tokio::spawn(async move {
+            if let Err(report) = handle_scrape(conn, &metrics, &backend).await {
+                warn!(%peer, error = %report, "Failed to serve metrics");
+            }
+        });
+    }
+}
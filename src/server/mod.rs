@@ -1,5 +1,20 @@
+pub mod accounts;
+pub mod admin;
 pub mod backend;
+pub mod blobs;
+pub mod cluster;
 pub mod communication;
+pub mod config;
+pub mod filter;
+pub mod hooks;
+pub mod irc;
+pub mod mentions;
+pub mod metrics;
+pub mod search;
+#[allow(clippy::module_inception)]
 pub mod server;
+pub mod validation;
 
+pub use config::Config;
+pub use hooks::Hook;
 pub use server::Server;
@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::server::communication::client::MessageId;
+
+/// Splits text into lowercase words
+pub fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// Inverted index from the words of the messages in a chat room history to their IDs
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// IDs of the messages containing a word
+    words: BTreeMap<String, BTreeSet<MessageId>>,
+}
+
+impl SearchIndex {
+    /// Adds the words of a message to the index
+    pub fn insert(&mut self, id: MessageId, content: &str) {
+        for word in words(content) {
+            self.words.entry(word).or_default().insert(id);
+        }
+    }
+
+    /// Removes a message that dropped out of the history from the index
+    pub fn remove(&mut self, id: MessageId, content: &str) {
+        for word in words(content) {
+            if let Some(ids) = self.words.get_mut(&word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// IDs of the messages that contain every word of the query, also as the start of longer
+    /// words, none if the query has no words
+    pub fn search(&self, query: &str) -> Option<BTreeSet<MessageId>> {
+        let mut terms = words(query).map(|term| {
+            self.words
+                .range(term.clone()..)
+                .take_while(|(word, _)| word.starts_with(&term))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect::<BTreeSet<_>>()
+        });
+
+        let first = terms.next()?;
+        Some(terms.fold(first, |found, ids| &found & &ids))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(messages: &[&str]) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        for (id, content) in messages.iter().enumerate() {
+            index.insert(id as MessageId, content);
+        }
+        index
+    }
+
+    fn ids(found: Option<BTreeSet<MessageId>>) -> Vec<MessageId> {
+        found.unwrap().into_iter().collect()
+    }
+
+    #[test]
+    fn words_are_split_on_punctuation_and_lowercased() {
+        let words: Vec<String> = words("Hello, World!\r\nit's 2 Grüße").collect();
+        assert_eq!(words, ["hello", "world", "it", "s", "2", "grüße"]);
+    }
+
+    #[test]
+    fn empty_and_whitespace_text_has_no_words() {
+        assert_eq!(words("").count(), 0);
+        assert_eq!(words(" \r\n\t ").count(), 0);
+        assert_eq!(words("!?-").count(), 0);
+    }
+
+    #[test]
+    fn queries_without_words_find_nothing_to_narrow() {
+        let index = index(&["hello"]);
+        assert_eq!(index.search(""), None);
+        assert_eq!(index.search("  ...  "), None);
+    }
+
+    #[test]
+    fn every_word_has_to_match() {
+        let index = index(&["red apple", "green apple", "red car"]);
+        assert_eq!(ids(index.search("apple")), [0, 1]);
+        assert_eq!(ids(index.search("RED apple")), [0]);
+        assert!(ids(index.search("green car")).is_empty());
+    }
+
+    #[test]
+    fn words_match_as_prefix() {
+        let index = index(&["deploying now", "deploy", "redeploy"]);
+        assert_eq!(ids(index.search("deploy")), [0, 1]);
+        assert!(ids(index.search("deployment")).is_empty());
+    }
+
+    #[test]
+    fn removed_messages_are_not_found() {
+        let mut index = index(&["hello world", "hello"]);
+        index.remove(0, "hello world");
+        assert_eq!(ids(index.search("hello")), [1]);
+        assert!(ids(index.search("world")).is_empty());
+        assert!(!index.words.contains_key("world"));
+    }
+}
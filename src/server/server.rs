@@ -1,33 +1,74 @@
-use futures_util::{
-    stream::{SplitSink, SplitStream, StreamExt},
-    SinkExt,
-};
+use bincode::Options;
+use futures_util::{stream::StreamExt, Sink, SinkExt, Stream};
 use miette::{miette, IntoDiagnostic, Result};
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
+    fs::File,
+    io::AsyncReadExt,
     net::{TcpListener, TcpStream},
-    sync::{broadcast, RwLock},
+    signal::unix::{signal, SignalKind},
+    sync::{broadcast, mpsc, watch, RwLock},
+};
+use tokio_tungstenite::{
+    accept_async_with_config,
+    tungstenite::{self, protocol::WebSocketConfig, Message},
 };
-use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+use tracing::{debug, error, field, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
 use crate::server::{
-    backend::Backend,
+    accounts::{self, Accounts},
+    admin::{self, ReloadRequest},
+    backend::{Backend, ChatRoom, ConnectionInfo, HandlerCommand, Owner},
+    blobs::{self, BlobStore, Upload, MAX_UPLOADS},
+    cluster::{self, Cluster},
     communication::{
         client::{
-            ChangeNameRequest, ClientMakeChatRoomRequest, ClientMessage, JoinChatRoomRequest,
-            SendMessageRequest,
+            AdminLoginRequest, AnnounceRequest, AttachmentId, ChangeNameRequest, ClientEnvelope,
+            ClientMakeChatRoomRequest, ClientMessage, CredentialsRequest, DirectMessageRequest,
+            DownloadRequest, FetchHistoryRequest, FetchKeyRequest, FetchThreadRequest,
+            FinishUploadRequest, IgnoreRequest, JoinChatRoomRequest, MarkReadRequest, MessageId,
+            Presence, PublishKeyRequest, ReactRequest, RequestId, RequestKind, ReviewHeldRequest,
+            SearchRequest, SendMessageRequest, SetFilterRequest, SetPresenceRequest,
+            StartUploadRequest, UploadChunkRequest, UploadId, WhoRequest, CHUNK_SIZE,
         },
-        server::{JoinChatRoomResponse, ListChatRoomsResponse, NewMessageRequest, ServerMessage},
-        ChatMessage,
+        error::{ErrorCode, ServerError},
+        server::{
+            AckResponse, AnnouncementResponse, Attachment, DirectMessageResponse,
+            DownloadChunkResponse, HeldMessagesResponse, HistoryResponse, IgnoredResponse,
+            JoinChatRoomResponse, LeftChatRoomResponse, ListChatRoomsResponse, MotdResponse,
+            NewMessageRequest, PresenceResponse, PublicKeyResponse, SearchResultsResponse,
+            ServerEnvelope, ServerMessage, ThreadResponse, TypingResponse, WhoResponse,
+        },
+        Broadcast, ChatMessage,
     },
+    config::{Config, ReloadReport},
+    filter::ContentFilter,
+    hooks::{Annotations, Hook, Hooks, RoomEvent, Session},
+    irc, mentions,
+    metrics::{self, Metrics},
+    validation,
 };
 
-/// Deserializes a msg from the client into a [`ClientMessage`]
-fn deserialize_client_msg(msg: Message) -> Result<ClientMessage> {
+/// Deserializes a msg from the client into a [`ClientEnvelope`], reading at most `limit` bytes
+fn deserialize_client_msg(msg: Message, limit: usize) -> Result<ClientEnvelope> {
     match msg {
         Message::Binary(bytes) => {
-            let message: ClientMessage = bincode::deserialize(&bytes).into_diagnostic()?;
+            // Same encoding as `bincode::deserialize`, but bounded length prefixes
+            let message: ClientEnvelope = bincode::options()
+                .with_fixint_encoding()
+                .allow_trailing_bytes()
+                .with_limit(limit as u64)
+                .deserialize(&bytes)
+                .into_diagnostic()?;
 
             Ok(message)
         }
@@ -35,61 +76,426 @@ fn deserialize_client_msg(msg: Message) -> Result<ClientMessage> {
     }
 }
 
-/// Serializes a [`ServerMessage`] into a tungestenite message
-fn serialize_server_msg(msg: ServerMessage) -> Result<Message> {
+/// Serializes a [`ServerEnvelope`] into a tungestenite message
+fn serialize_server_msg(msg: ServerEnvelope) -> Result<Message> {
     let client_msg = bincode::serialize(&msg).into_diagnostic()?;
     let serialized_msg = Message::Binary(client_msg);
     Ok(serialized_msg)
 }
 
-async fn send_server_msg_over_socket(
-    socket: &mut SplitSink<WebSocketStream<TcpStream>, Message>,
+/// Sends a [`ServerMessage`] over a websocket or its sending half, as reply to the given request
+async fn send_server_msg_over_socket<S>(
+    socket: &mut S,
+    request_id: Option<RequestId>,
     server_msg: ServerMessage,
-) -> Result<()> {
+) -> Result<()>
+where
+    S: Sink<Message, Error = tungstenite::Error> + Unpin,
+{
+    let envelope = ServerEnvelope::new(request_id, server_msg);
     socket
-        .send(serialize_server_msg(server_msg)?)
+        .send(serialize_server_msg(envelope)?)
         .await
         .into_diagnostic()
 }
 
+/// Sending half of the connection to a client
+pub type Outgoing = Pin<Box<dyn Sink<Message, Error = tungstenite::Error> + Send>>;
+
+/// Receiving half of the connection to a client
+pub type Incoming = Pin<Box<dyn Stream<Item = Result<Message, tungstenite::Error>> + Send>>;
+
+/// Shortest time between two typing events of a client that are passed on to its chat room
+const TYPING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reason a handler stopped serving its client
+#[derive(Debug)]
+enum Disconnect {
+    /// The client closed the websocket
+    ClientClosed,
+    /// The client got banned by a config reload
+    Banned,
+    /// An operator disconnected the client
+    Kicked,
+    /// The client sent a frame above the size limit
+    FrameTooLarge,
+}
+
 /// Contains the logic for running the server
 pub struct Server {
     /// The socket the server listens on
     socket_addr: SocketAddr,
     /// Datastructure to keep track of all backrooms, etc.
     backend: Arc<RwLock<Backend>>,
+    /// File the configuration was loaded from, if any
+    config_path: Option<PathBuf>,
+    /// Current configuration, watched by all handlers
+    config: watch::Sender<Config>,
+    /// Instrumentation shared with all handlers
+    metrics: Arc<Metrics>,
+    /// Address to serve the metrics on, if enabled
+    metrics_addr: Option<SocketAddr>,
+    /// Path of the admin unix socket, if enabled
+    admin_socket: Option<PathBuf>,
+    /// Hooks called by all handlers
+    hooks: Hooks,
+    /// Relays rooms and membership to the other instances of the cluster
+    cluster: Cluster,
+    /// Address to accept links from other instances on, if clustering is enabled
+    cluster_addr: Option<SocketAddr>,
+    /// Addresses of the other instances of the cluster
+    peers: Vec<SocketAddr>,
+    /// Address to accept IRC clients on, if the gateway is enabled
+    irc_addr: Option<SocketAddr>,
 }
 
 impl Server {
-    /// Instantiates a new server that listens on the given socket
-    pub fn new(socket_addr: SocketAddr) -> Self {
-        return Server {
+    /// Instantiates a new server that listens on the given socket, loads the registered
+    /// identities and attachments from the data directory
+    pub fn new(socket_addr: SocketAddr, config: Config) -> Result<Self> {
+        let (accounts, blobs) = match &config.data_dir {
+            Some(dir) => (
+                Accounts::load(dir)?,
+                Some(BlobStore::open(dir.join("blobs"))?),
+            ),
+            None => (Accounts::default(), None),
+        };
+
+        let cluster = Cluster::default();
+        let mut backend = Backend::new(
+            config.room_capacity,
+            config.history_len,
+            accounts,
+            blobs,
+            cluster.clone(),
+        );
+        for room in &config.default_rooms {
+            backend.ensure_room(room.clone());
+        }
+
+        let (config, _) = watch::channel(config);
+
+        Ok(Server {
             socket_addr,
-            backend: Arc::new(RwLock::new(Backend::new())),
+            backend: Arc::new(RwLock::new(backend)),
+            config_path: None,
+            config,
+            metrics: Arc::new(Metrics::default()),
+            metrics_addr: None,
+            admin_socket: None,
+            hooks: Hooks::default(),
+            cluster,
+            cluster_addr: None,
+            peers: Vec::new(),
+            irc_addr: None,
+        })
+    }
+
+    /// Sets the file the configuration is reloaded from
+    pub fn config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+
+    /// Enables the prometheus metrics endpoint on the given address
+    pub fn metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Enables the admin interface on a unix socket at the given path
+    pub fn admin_socket(mut self, path: PathBuf) -> Self {
+        self.admin_socket = Some(path);
+        self
+    }
+
+    /// Accepts links from the other instances of the cluster on the given address
+    pub fn cluster_addr(mut self, addr: SocketAddr) -> Self {
+        self.cluster_addr = Some(addr);
+        self
+    }
+
+    /// Relays rooms and membership to another instance of the cluster, which has to be
+    /// accepting links on the given address
+    pub fn peer(mut self, addr: SocketAddr) -> Self {
+        self.peers.push(addr);
+        self
+    }
+
+    /// Accepts IRC clients on the given address, which chat in the same rooms
+    pub fn irc_addr(mut self, addr: SocketAddr) -> Self {
+        self.irc_addr = Some(addr);
+        self
+    }
+
+    /// Adds a hook that is called for every client message and room event
+    pub fn hook(mut self, hook: impl Hook + 'static) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Reloads the configuration file and applies it to the running server
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        let path = self
+            .config_path
+            .as_ref()
+            .ok_or(miette!("Server was not started with a config file"))?;
+
+        let new_config = Config::load(path)?;
+        let report = self.config.borrow().diff(&new_config);
+
+        let mut backend = self.backend.write().await;
+        for room in &new_config.default_rooms {
+            backend.ensure_room(room.clone());
+        }
+
+        // Handlers pick up the new config through their watch receivers
+        self.config.send_replace(new_config);
+
+        Ok(report)
+    }
+
+    /// Reloads the configuration file and logs the outcome
+    async fn reload_logged(&self) -> Result<ReloadReport> {
+        let result = self.reload().await;
+
+        match &result {
+            Ok(report) => {
+                info!(applied = ?report.applied, "Reloaded config");
+
+                if !report.requires_restart.is_empty() {
+                    warn!(
+                        settings = ?report.requires_restart,
+                        "Some settings require a restart"
+                    );
+                }
+
+                if !report.requires_reconnect.is_empty() {
+                    warn!(
+                        settings = ?report.requires_reconnect,
+                        "Some settings only apply to new connections"
+                    );
+                }
+            }
+            Err(report) => error!(error = %report, "Failed to reload config"),
+        }
+
+        result
+    }
+
+    /// Sets up the websocket connection and handler for a new client
+    async fn accept(&self, conn: TcpStream, addr: SocketAddr) {
+        if self.config.borrow().is_banned_addr(&addr.ip()) {
+            warn!(peer = %addr, "Refused connection from banned address");
+            return;
+        }
+
+        let max_frame_size = self.config.borrow().limits.max_frame_size;
+        let ws_config = WebSocketConfig {
+            max_frame_size: Some(max_frame_size),
+            max_message_size: Some(max_frame_size),
+            ..Default::default()
         };
+
+        match accept_async_with_config(conn, Some(ws_config)).await {
+            Ok(mut ws) => {
+                let motd = self.config.borrow().motd.clone();
+                if let Some(motd) = motd {
+                    let server_msg = ServerMessage::Motd(MotdResponse::new(motd));
+
+                    if let Err(report) =
+                        send_server_msg_over_socket(&mut ws, None, server_msg).await
+                    {
+                        warn!(peer = %addr, error = %report, "Failed to send message of the day");
+                        return;
+                    }
+                }
+
+                let (ws_send, ws_recv) = ws.split();
+                self.start_handler(addr, Box::pin(ws_send), Box::pin(ws_recv))
+                    .await;
+            }
+            Err(err) => {
+                warn!(peer = %addr, error = %err, "Websocket handshake failed");
+            }
+        }
+    }
+
+    /// Sets up the gateway and handler for a new IRC client
+    async fn accept_irc(&self, conn: TcpStream, addr: SocketAddr) {
+        if self.config.borrow().is_banned_addr(&addr.ip()) {
+            warn!(peer = %addr, "Refused IRC connection from banned address");
+            return;
+        }
+
+        let (motd, max_line_len) = {
+            let config = self.config.borrow();
+            (config.motd.clone(), config.limits.max_frame_size)
+        };
+
+        // The handler serves the gateway like a websocket client
+        let (request_send, request_recv) = futures_channel::mpsc::channel(irc::CHANNEL_CAPACITY);
+        let (reply_send, reply_recv) = futures_channel::mpsc::channel(irc::CHANNEL_CAPACITY);
+        let ws_send = reply_send.sink_map_err(|_| tungstenite::Error::ConnectionClosed);
+
+        tokio::spawn(
+            async move {
+                if let Err(report) =
+                    irc::serve(conn, motd, max_line_len, request_send, reply_recv).await
+                {
+                    warn!(error = %report, "IRC gateway failed");
+                }
+            }
+            .instrument(info_span!("irc", peer = %addr)),
+        );
+
+        self.start_handler(addr, Box::pin(ws_send), Box::pin(request_recv))
+            .await;
+    }
+
+    /// Registers a new client with the backend and spawns its handler
+    async fn start_handler(&self, addr: SocketAddr, ws_send: Outgoing, ws_recv: Incoming) {
+        let b = self.backend.clone();
+        let uuid = Uuid::new_v4();
+        let metrics = self.metrics.clone();
+
+        let (control_send, control_recv) = mpsc::unbounded_channel();
+        let info = ConnectionInfo {
+            uuid,
+            name: "anonymous".to_string(),
+            identity: None,
+            addr,
+            room: None,
+            presence: Presence::Online,
+            status: None,
+        };
+        b.write().await.connect(info, control_send);
+
+        let handler = Handler::new(
+            uuid,
+            addr,
+            ws_send,
+            ws_recv,
+            b.clone(),
+            self.config.subscribe(),
+            metrics.clone(),
+            self.hooks.clone(),
+            self.cluster.clone(),
+            control_recv,
+        );
+        let span = info_span!("connection", %uuid, peer = %addr);
+
+        tokio::spawn(
+            async move {
+                metrics.handler_started();
+
+                match handler.run().await {
+                    Ok(reason) => {
+                        info!(?reason, "Handler terminated");
+                        metrics.handler_finished(false);
+                    }
+                    Err(report) => {
+                        error!(error = %report, "Handler terminated with an error");
+                        metrics.handler_finished(true);
+                    }
+                }
+
+                b.write().await.disconnect(&uuid);
+            }
+            .instrument(span),
+        );
     }
 
     /// Starts the server and handles connection to the socket
     pub async fn run(&self) {
-        println!("Starting server");
+        info!(addr = %self.socket_addr, "Starting server");
 
         // Instatiate listener for incoming connections
         let listener = TcpListener::bind(self.socket_addr).await.unwrap();
+        let irc_listener = match self.irc_addr {
+            Some(addr) => {
+                info!(%addr, "Accepting IRC clients");
+                Some(TcpListener::bind(addr).await.unwrap())
+            }
+            None => None,
+        };
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
 
-        while let Ok((conn, addr)) = listener.accept().await {
-            println!("Connection accepted with address: {addr:?}");
+        if let Some(writer) = self.backend.read().await.accounts().writer() {
+            tokio::spawn(writer);
+        }
 
-            // Setup websocket connection and handler
-            match accept_async(conn).await {
-                Ok(ws) => {
-                    let b = self.backend.clone();
-                    let uuid = Uuid::new_v4();
-                    let handler = Handler::new(uuid, ws, b);
+        if let Some(blobs) = self.backend.read().await.blobs() {
+            tokio::spawn(blobs::expire(blobs, self.backend.clone()));
+        }
 
-                    tokio::spawn(async move { handler.run().await });
+        let (reload_send, mut reload_recv) = mpsc::channel::<ReloadRequest>(1);
+
+        if let Some(path) = self.admin_socket.clone() {
+            let backend = self.backend.clone();
+
+            tokio::spawn(async move {
+                if let Err(report) = admin::serve(path, backend, reload_send).await {
+                    error!(error = %report, "Admin interface stopped");
                 }
-                Err(err) => {
-                    println!("Failed to connect {err:?}");
+            });
+        }
+
+        if let Some(addr) = self.metrics_addr {
+            let metrics = self.metrics.clone();
+            let backend = self.backend.clone();
+
+            tokio::spawn(async move {
+                if let Err(report) = metrics::serve(addr, metrics, backend).await {
+                    error!(error = %report, "Metrics endpoint stopped");
+                }
+            });
+        }
+
+        if let Some(addr) = self.cluster_addr {
+            let cluster = self.cluster.clone();
+            let backend = self.backend.clone();
+            let config = self.config.subscribe();
+
+            tokio::spawn(async move {
+                if let Err(report) = cluster::serve(addr, cluster, backend, config).await {
+                    error!(error = %report, "Cluster endpoint stopped");
+                }
+            });
+        }
+
+        for &addr in &self.peers {
+            tokio::spawn(cluster::connect(
+                addr,
+                self.cluster.clone(),
+                self.backend.clone(),
+                self.config.subscribe(),
+            ));
+        }
+
+        loop {
+            tokio::select! {
+                Ok((conn, addr)) = listener.accept() => {
+                    info!(peer = %addr, "Connection accepted");
+                    self.accept(conn, addr).await;
+                }
+                // Never completes when the gateway is disabled
+                Ok((conn, addr)) = async {
+                    match &irc_listener {
+                        Some(listener) => listener.accept().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    info!(peer = %addr, "IRC connection accepted");
+                    self.accept_irc(conn, addr).await;
+                }
+                Some(()) = hangup.recv() => {
+                    info!("Received SIGHUP, reloading config");
+                    let _ = self.reload_logged().await;
+                }
+                Some(reply) = reload_recv.recv() => {
+                    info!("Reloading config on request of an operator");
+                    let _ = reply.send(self.reload_logged().await);
                 }
             }
         }
@@ -100,131 +506,1052 @@ impl Server {
 pub struct Handler {
     /// ID for the client handled by this handler
     uuid: Uuid,
+    /// Address of the client
+    addr: SocketAddr,
     /// User name
     name: String,
+    /// Registered identity the client logged in as
+    identity: Option<String>,
+    /// Identities whose messages are not delivered to the client, stored with its identity
+    ignored: BTreeSet<String>,
+    /// Name of the chat room the client is in
+    room: Option<String>,
     /// Backend that keeps track of all chatrooms etc
     backend: Arc<RwLock<Backend>>,
+    /// Current server configuration
+    config: watch::Receiver<Config>,
+    /// Server instrumentation
+    metrics: Arc<Metrics>,
+    /// Hooks called for client messages and room events
+    hooks: Hooks,
+    /// Relays what the client sends to its chat room to the other instances of the cluster
+    cluster: Cluster,
+    /// Whether the client logged in as admin
+    is_admin: bool,
+    /// Presence the client set, mentions do not highlight while it does not want to be disturbed
+    presence: Presence,
+    /// Uploads in progress
+    uploads: HashMap<UploadId, Upload>,
+    /// Finished uploads, which the client may attach to its messages
+    attachments: HashMap<AttachmentId, Attachment>,
+    /// Start of the current rate limit window
+    window_start: Instant,
+    /// Number of messages sent in the current rate limit window
+    window_count: u32,
+    /// When the last typing event of the client was passed on
+    typing_at: Option<Instant>,
     /// Websocket sender
-    ws_send: SplitSink<WebSocketStream<TcpStream>, Message>,
+    ws_send: Outgoing,
     /// Websocket receiver
-    ws_recv: SplitStream<WebSocketStream<TcpStream>>,
+    ws_recv: Incoming,
     /// Channel to send a message into a chat room
-    room_send: broadcast::Sender<ChatMessage>,
+    room_send: broadcast::Sender<Broadcast>,
     /// Channel to receive messages from a chat room
-    room_recv: broadcast::Receiver<ChatMessage>,
+    room_recv: broadcast::Receiver<Broadcast>,
+    /// Channel to receive instructions from the server
+    control: mpsc::UnboundedReceiver<HandlerCommand>,
 }
 
 impl Handler {
     /// Instantiates a new handler
-    pub fn new(uuid: Uuid, ws: WebSocketStream<TcpStream>, backend: Arc<RwLock<Backend>>) -> Self {
-        let (ws_send, ws_recv) = ws.split();
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        uuid: Uuid,
+        addr: SocketAddr,
+        ws_send: Outgoing,
+        ws_recv: Incoming,
+        backend: Arc<RwLock<Backend>>,
+        config: watch::Receiver<Config>,
+        metrics: Arc<Metrics>,
+        hooks: Hooks,
+        cluster: Cluster,
+        control: mpsc::UnboundedReceiver<HandlerCommand>,
+    ) -> Self {
         let (room_send, room_recv) = broadcast::channel(1);
         let name = "anonymous".to_string();
 
         Handler {
             uuid,
+            addr,
             name,
+            identity: None,
+            ignored: BTreeSet::new(),
+            room: None,
             backend,
+            config,
+            metrics,
+            hooks,
+            cluster,
+            is_admin: false,
+            presence: Presence::Online,
+            uploads: HashMap::new(),
+            attachments: HashMap::new(),
+            window_start: Instant::now(),
+            window_count: 0,
+            typing_at: None,
             ws_send,
             ws_recv,
             room_send,
             room_recv,
+            control,
+        }
+    }
+
+    /// Counts a chat message against the rate limit, returns false if it is exceeded
+    fn within_rate_limit(&mut self) -> bool {
+        let limit = self.config.borrow().rate_limit.clone();
+        let now = Instant::now();
+
+        if now.duration_since(self.window_start) >= limit.window() {
+            self.window_start = now;
+            self.window_count = 0;
+        }
+
+        self.window_count += 1;
+        self.window_count <= limit.messages
+    }
+
+    /// Checks whether the client is banned under the current config
+    fn is_banned(&self) -> bool {
+        let config = self.config.borrow();
+        config.is_banned_name(&self.name) || config.is_banned_addr(&self.addr.ip())
+    }
+
+    /// Owner of the chat rooms the client creates, its identity if it is logged in
+    fn owner(&self) -> Owner {
+        match &self.identity {
+            Some(identity) => Owner::Identity(identity.clone()),
+            None => Owner::Connection(self.uuid),
+        }
+    }
+
+    /// Returns the current chat room if the client may moderate it
+    fn moderated_room<'b>(
+        &self,
+        backend: &'b mut Backend,
+    ) -> Result<&'b mut ChatRoom, ServerError> {
+        let name = self
+            .room
+            .as_deref()
+            .ok_or_else(|| ServerError::new(ErrorCode::RoomNotFound))?;
+        let room = backend.get_room_mut(name)?;
+
+        if room.is_owner(&self.uuid, self.identity.as_deref()) || self.is_admin {
+            Ok(room)
+        } else {
+            Err(ServerError::new(ErrorCode::NotRoomOwner).details(name))
+        }
+    }
+
+    /// Checks whether the client ignores the given identity
+    fn is_ignoring(&self, identity: Option<&str>) -> bool {
+        identity.is_some_and(|identity| self.ignored.contains(identity))
+    }
+
+    /// Removes the messages of ignored users from a response
+    fn without_ignored(&self, mut messages: Vec<NewMessageRequest>) -> Vec<NewMessageRequest> {
+        messages.retain(|message| !self.is_ignoring(message.identity.as_deref()));
+        messages
+    }
+
+    /// Sends to the members of the chat room, on this instance and the others of the cluster
+    fn broadcast(&self, broadcast: Broadcast) {
+        if let Some(room) = &self.room {
+            self.cluster.relay_broadcast(room, &broadcast);
         }
+        let _ = self.room_send.send(broadcast);
+    }
+
+    /// Describes the client to the hooks
+    fn session(&self) -> Session<'_> {
+        Session {
+            uuid: self.uuid,
+            addr: self.addr,
+            name: &self.name,
+            room: self.room.as_deref(),
+            is_admin: self.is_admin,
+        }
+    }
+
+    /// Replies to a request of the client
+    async fn reply(
+        &mut self,
+        request_id: Option<RequestId>,
+        server_msg: ServerMessage,
+    ) -> Result<()> {
+        send_server_msg_over_socket(&mut self.ws_send, request_id, server_msg).await
+    }
+
+    /// Confirms that a request of the client succeeded
+    async fn ack(&mut self, request_id: Option<RequestId>, request: RequestKind) -> Result<()> {
+        let server_msg = ServerMessage::Ack(AckResponse::new(request));
+        self.reply(request_id, server_msg).await
+    }
+
+    /// Reports a rejected request to the client
+    async fn reject(&mut self, request_id: Option<RequestId>, error: ServerError) -> Result<()> {
+        debug!(code = error.code.name(), error = %error, "Rejected request");
+        self.metrics.rejected(error.code.name());
+
+        self.reply(request_id, ServerMessage::Err(error)).await
     }
 
     /// Handles messages from the client
     async fn handle_client_msg(&mut self, msg: Message) -> Result<()> {
-        let message = deserialize_client_msg(msg)?;
+        let limit = self.config.borrow().limits.max_frame_size;
+        let ClientEnvelope {
+            request_id,
+            mut message,
+        } = match deserialize_client_msg(msg, limit) {
+            Ok(envelope) => envelope,
+            Err(report) => {
+                let error = ServerError::new(ErrorCode::InvalidMessage).details(report.to_string());
+                return self.reject(None, error).await;
+            }
+        };
+
+        let mut annotations = Annotations::new();
+        let checked = self
+            .hooks
+            .before(&self.session(), &mut message, &mut annotations);
+        if !annotations.is_empty() {
+            debug!(?annotations, "Request annotated by hooks");
+        }
+
+        // Hooks may have rewritten the input, so it is validated afterwards
+        let checked =
+            checked.and_then(|()| validation::validate(&message, &self.config.borrow().limits));
+
+        let request = message.kind();
+        let outcome = match checked {
+            Ok(()) => self.handle_request(request_id, message.clone()).await?,
+            Err(error) => Err(error),
+        };
+
+        self.hooks.after(
+            &self.session(),
+            &message,
+            &annotations,
+            outcome.as_ref().copied(),
+        );
+
+        if let Err(error) = outcome {
+            self.reject(request_id, error.request(request)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Handles a single request, the returned server error is reported to the client
+    async fn handle_request(
+        &mut self,
+        request_id: Option<RequestId>,
+        message: ClientMessage,
+    ) -> Result<Result<(), ServerError>> {
+        let request = message.kind();
 
         match message {
             ClientMessage::MakeChatRoom(ClientMakeChatRoomRequest { name }) => {
-                let mut backend = self.backend.write().await;
-
-                if let Err(report) = backend.new_room(name) {
-                    let server_msg = ServerMessage::Err(report.to_string());
-                    send_server_msg_over_socket(&mut self.ws_send, server_msg).await?;
+                let result = self
+                    .backend
+                    .write()
+                    .await
+                    .new_room(name.clone(), Some(self.owner()));
+                if let Err(error) = result {
+                    return Ok(Err(error));
                 }
+
+                self.hooks
+                    .room_event(&self.session(), RoomEvent::Created(&name));
+                self.ack(request_id, request).await?;
             }
             ClientMessage::ListChatRooms() => {
                 let backend = self.backend.read().await;
                 let rooms = backend.list();
-                let server_msg = ServerMessage::ListChatRooms(ListChatRoomsResponse::new(rooms));
-                send_server_msg_over_socket(&mut self.ws_send, server_msg).await?;
+                let unread = self
+                    .identity
+                    .as_deref()
+                    .map(|identity| backend.unread(identity))
+                    .unwrap_or_default();
+                let server_msg =
+                    ServerMessage::ListChatRooms(ListChatRoomsResponse::new(rooms, unread));
+                drop(backend);
+                self.reply(request_id, server_msg).await?;
             }
             ClientMessage::JoinChatRoom(JoinChatRoomRequest { name }) => {
-                let backend = self.backend.write().await;
+                let backend = self.backend.clone();
+                let mut backend = backend.write().await;
+
+                let room = match backend.get_room(name.clone()) {
+                    Ok(room) => room,
+                    Err(error) => return Ok(Err(error)),
+                };
+                let new_recv = room.subscribe(&self.name);
+                let new_send = room.publish();
+
+                let server_msg =
+                    ServerMessage::JoinedChatRoom(JoinChatRoomResponse::new(name.clone()));
+                self.reply(request_id, server_msg).await?;
+
+                self.broadcast(Broadcast::Message(ChatMessage::new(
+                    self.uuid.to_string(),
+                    self.name.clone(),
+                    format!("User {} left the chat room", self.name),
+                )));
+                if let Some(previous) = &self.room {
+                    self.hooks
+                        .room_event(&self.session(), RoomEvent::Left(previous));
+                }
+
+                self.room_send = new_send;
+                self.room_recv = new_recv;
+
+                backend.set_room(&self.uuid, Some(name.clone()));
+                info!(room = %name, "Joined chat room");
+                self.room = Some(name.clone());
+                self.hooks
+                    .room_event(&self.session(), RoomEvent::Joined(&name));
+            }
+            ClientMessage::SendMessage(SendMessageRequest { .. })
+            | ClientMessage::DirectMessage(DirectMessageRequest { .. })
+                if !self.within_rate_limit() =>
+            {
+                return Ok(Err(ServerError::new(ErrorCode::RateLimited)));
+            }
+            ClientMessage::SendMessage(SendMessageRequest {
+                content,
+                reply_to,
+                attachment,
+            }) => {
+                let mut message =
+                    ChatMessage::new(self.uuid.to_string(), self.name.clone(), content);
+                message.sender_identity = self.identity.clone();
 
-                match backend.get_room(name.clone()) {
-                    Ok(room) => {
-                        let new_recv = room.subscribe(&self.name);
-                        let new_send = room.publish();
+                if let Some(id) = attachment {
+                    // Uploads no message refers to expire, also those the client still holds
+                    let blobs = self.backend.read().await.blobs();
+                    let stored = match &blobs {
+                        Some(blobs) => blobs.contains(&id).await,
+                        None => false,
+                    };
+                    if !stored {
+                        self.attachments.remove(&id);
+                    }
+
+                    match self.attachments.get(&id) {
+                        Some(attachment) => message.attachment = Some(attachment.clone()),
+                        None => {
+                            return Ok(Err(
+                                ServerError::new(ErrorCode::AttachmentNotFound).details(id)
+                            ))
+                        }
+                    }
+                }
+
+                let backend = self.backend.clone();
+                let mut backend = backend.write().await;
+
+                message.mentions = mentions::parse(&message.content)
+                    .into_iter()
+                    .filter(|name| backend.has_name(name))
+                    .collect();
 
-                        let server_msg =
-                            ServerMessage::JoinedChatRoom(JoinChatRoomResponse::new(name));
-                        send_server_msg_over_socket(&mut self.ws_send, server_msg).await?;
+                match self.room.as_deref().map(|room| backend.get_room_mut(room)) {
+                    Some(Ok(chat_room)) => {
+                        let posted = reply_to
+                            .map_or(Ok(()), |id| chat_room.attach_reply(&mut message, id))
+                            .and_then(|()| chat_room.filter(message))
+                            .map(|message| chat_room.post(message));
+                        drop(backend);
 
+                        let id = match posted {
+                            Ok(id) => id,
+                            Err(error) => return Ok(Err(error)),
+                        };
+
+                        if let Some(room) = &self.room {
+                            self.metrics.message_sent(room);
+                        }
+
+                        let server_msg = ServerMessage::Ack(AckResponse::sent(request, id));
+                        self.reply(request_id, server_msg).await?;
+                    }
+                    // Outside of a chat room, or in one that was just deleted, nobody receives it
+                    _ => {
+                        drop(backend);
                         self.room_send
-                            .send(ChatMessage {
-                                sender_uuid: self.uuid.to_string(),
-                                sender_name: self.name.clone(),
-                                content: format!("User {} left the chat room", self.name),
-                            })
+                            .send(Broadcast::Message(message))
                             .into_diagnostic()?;
-
-                        self.room_send = new_send;
-                        self.room_recv = new_recv;
+                        self.ack(request_id, request).await?;
                     }
-                    Err(report) => {
-                        let server_msg = ServerMessage::Err(report.to_string());
-                        send_server_msg_over_socket(&mut self.ws_send, server_msg).await?;
+                }
+            }
+            ClientMessage::StartUpload(StartUploadRequest { upload_id, .. })
+                if self.uploads.len() >= MAX_UPLOADS && !self.uploads.contains_key(&upload_id) =>
+            {
+                return Ok(Err(ServerError::new(ErrorCode::RateLimited)
+                    .details(format!("at most {MAX_UPLOADS} uploads at a time"))));
+            }
+            ClientMessage::StartUpload(start) => {
+                let Some(blobs) = self.backend.read().await.blobs() else {
+                    return Ok(Err(ServerError::new(ErrorCode::AttachmentsDisabled)));
+                };
+
+                // Starting again with the same ID discards the earlier attempt
+                let upload_id = start.upload_id;
+                if let Some(previous) = self.uploads.remove(&upload_id) {
+                    previous.abort().await;
+                }
+
+                let quota = self.config.borrow().limits.max_blob_storage;
+                let upload = match blobs.upload(start, quota).await? {
+                    Ok(upload) => upload,
+                    Err(error) => return Ok(Err(error)),
+                };
+                self.uploads.insert(upload_id, upload);
+
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::UploadChunk(UploadChunkRequest {
+                upload_id,
+                offset,
+                data,
+            }) => {
+                let Some(upload) = self.uploads.get_mut(&upload_id) else {
+                    return Ok(Err(
+                        ServerError::new(ErrorCode::UploadNotFound).details(upload_id.to_string())
+                    ));
+                };
+
+                if let Err(error) = upload.write(offset, &data).await? {
+                    if let Some(upload) = self.uploads.remove(&upload_id) {
+                        upload.abort().await;
                     }
+                    return Ok(Err(error));
+                }
+
+                // Chunks are not acknowledged one by one, errors are reported as usual
+            }
+            ClientMessage::FinishUpload(FinishUploadRequest { upload_id }) => {
+                let Some(upload) = self.uploads.remove(&upload_id) else {
+                    return Ok(Err(
+                        ServerError::new(ErrorCode::UploadNotFound).details(upload_id.to_string())
+                    ));
+                };
+                let attachment = match upload.finish().await? {
+                    Ok(attachment) => attachment,
+                    Err(error) => return Ok(Err(error)),
+                };
+
+                info!(id = %attachment.id, size = attachment.size, "Stored attachment");
+                self.attachments.insert(attachment.id.clone(), attachment);
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::Download(DownloadRequest { attachment_id, .. }) => {
+                return self.download(request_id, attachment_id).await;
+            }
+            // Typing events are neither acknowledged nor stored, too frequent ones are dropped
+            ClientMessage::Typing() => {
+                let throttled = self
+                    .typing_at
+                    .is_some_and(|typing_at| typing_at.elapsed() < TYPING_INTERVAL);
+
+                if self.room.is_some() && !throttled {
+                    self.typing_at = Some(Instant::now());
+                    let typing = TypingResponse::new(self.name.clone(), self.identity.clone());
+                    self.broadcast(Broadcast::Typing(typing));
                 }
             }
-            ClientMessage::SendMessage(SendMessageRequest { content }) => {
-                self.room_send
-                    .send(ChatMessage {
-                        sender_uuid: self.uuid.to_string(),
-                        sender_name: self.name.clone(),
-                        content,
-                    })
-                    .into_diagnostic()?;
+            ClientMessage::SetPresence(SetPresenceRequest { presence, status }) => {
+                self.presence = presence;
+                self.backend
+                    .write()
+                    .await
+                    .set_presence(&self.uuid, presence, status.clone());
+                debug!(?presence, "Changed presence");
+
+                let update = PresenceResponse::new(
+                    self.name.clone(),
+                    self.identity.clone(),
+                    presence,
+                    status,
+                );
+                self.broadcast(Broadcast::Presence(update));
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::LeaveChatRoom() => {
+                let Some(name) = self.room.clone() else {
+                    return Ok(Err(ServerError::new(ErrorCode::RoomNotFound)));
+                };
+
+                self.broadcast(Broadcast::Message(ChatMessage::new(
+                    self.uuid.to_string(),
+                    self.name.clone(),
+                    format!("User {} left the chat room", self.name),
+                )));
+                self.hooks
+                    .room_event(&self.session(), RoomEvent::Left(&name));
+
+                // Fall back to a private channel, like a freshly connected client
+                let (room_send, room_recv) = broadcast::channel(1);
+                self.room_send = room_send;
+                self.room_recv = room_recv;
+                self.room = None;
+                self.backend.write().await.set_room(&self.uuid, None);
+                info!(room = %name, "Left chat room");
+
+                let server_msg = ServerMessage::LeftChatRoom(LeftChatRoomResponse::new(name));
+                self.reply(request_id, server_msg).await?;
+            }
+            ClientMessage::Who(WhoRequest { room }) => {
+                let backend = self.backend.read().await;
+                if let Err(error) = backend.get_room(room.clone()) {
+                    return Ok(Err(error));
+                }
+                let users = backend.who(&room);
+                drop(backend);
+
+                let server_msg = ServerMessage::Who(WhoResponse::new(room, users));
+                self.reply(request_id, server_msg).await?;
+            }
+            // Sealed content is passed on as it is, only the recipient can decrypt it
+            ClientMessage::DirectMessage(DirectMessageRequest {
+                to,
+                content,
+                sealed,
+            }) => {
+                let message = DirectMessageResponse::new(
+                    self.name.clone(),
+                    self.identity.clone(),
+                    content,
+                    sealed,
+                );
+                let result = self.backend.write().await.direct_message(&to, message);
+                if let Err(error) = result {
+                    return Ok(Err(error));
+                }
+
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::React(ReactRequest { message_id, emoji }) => {
+                return self
+                    .react(request_id, request, message_id, emoji, true)
+                    .await;
+            }
+            ClientMessage::Unreact(ReactRequest { message_id, emoji }) => {
+                return self
+                    .react(request_id, request, message_id, emoji, false)
+                    .await;
+            }
+            ClientMessage::FetchThread(FetchThreadRequest { id }) => {
+                let Some(room) = self.room.clone() else {
+                    return Ok(Err(ServerError::new(ErrorCode::RoomNotFound)));
+                };
+
+                let thread = self
+                    .backend
+                    .read()
+                    .await
+                    .get_room(room.clone())
+                    .and_then(|chat_room| chat_room.thread(id));
+                let messages = match thread {
+                    Ok(messages) => self.without_ignored(messages),
+                    Err(error) => return Ok(Err(error)),
+                };
+
+                let server_msg = ServerMessage::Thread(ThreadResponse::new(room, messages));
+                self.reply(request_id, server_msg).await?;
+            }
+            ClientMessage::FetchHistory(FetchHistoryRequest {
+                room,
+                before_id,
+                limit,
+            }) => {
+                let limit = limit.clamp(1, self.config.borrow().limits.max_page_len);
+
+                let page = self
+                    .backend
+                    .read()
+                    .await
+                    .get_room(room.clone())
+                    .map(|chat_room| chat_room.history(before_id, limit));
+                let (messages, next_before_id) = match page {
+                    Ok(page) => page,
+                    Err(error) => return Ok(Err(error)),
+                };
+                let messages = self.without_ignored(messages);
+
+                let server_msg =
+                    ServerMessage::History(HistoryResponse::new(room, messages, next_before_id));
+                self.reply(request_id, server_msg).await?;
+            }
+            ClientMessage::Search(search) => {
+                let limit = search
+                    .limit
+                    .clamp(1, self.config.borrow().limits.max_page_len);
+
+                let results = self
+                    .backend
+                    .read()
+                    .await
+                    .get_room(search.room.clone())
+                    .map(|chat_room| chat_room.search(&search, limit));
+                let (messages, next_before_id) = match results {
+                    Ok(results) => results,
+                    Err(error) => return Ok(Err(error)),
+                };
+                let messages = self.without_ignored(messages);
+
+                let SearchRequest { room, query, .. } = search;
+                let server_msg = ServerMessage::SearchResults(SearchResultsResponse::new(
+                    room,
+                    query,
+                    messages,
+                    next_before_id,
+                ));
+                self.reply(request_id, server_msg).await?;
+            }
+            ClientMessage::SetFilter(SetFilterRequest { filter }) => {
+                let compiled = match filter.as_ref().map(ContentFilter::new).transpose() {
+                    Ok(compiled) => compiled,
+                    Err(error) => return Ok(Err(error)),
+                };
+
+                let backend = self.backend.clone();
+                let mut backend = backend.write().await;
+                match self.moderated_room(&mut backend) {
+                    Ok(room) => room.set_filter(compiled),
+                    Err(error) => return Ok(Err(error)),
+                }
+                drop(backend);
+
+                info!(?filter, "Set chat room filter");
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::ListHeld() => {
+                let backend = self.backend.clone();
+                let mut backend = backend.write().await;
+                let held = match self.moderated_room(&mut backend) {
+                    Ok(room) => room.held(),
+                    Err(error) => return Ok(Err(error)),
+                };
+                drop(backend);
+
+                let room = self.room.clone().unwrap_or_default();
+                let server_msg = ServerMessage::HeldMessages(HeldMessagesResponse::new(room, held));
+                self.reply(request_id, server_msg).await?;
+            }
+            ClientMessage::ReviewHeld(ReviewHeldRequest { id, approve }) => {
+                let backend = self.backend.clone();
+                let mut backend = backend.write().await;
+                if let Err(error) = self
+                    .moderated_room(&mut backend)
+                    .and_then(|room| room.review(id, approve))
+                {
+                    return Ok(Err(error));
+                }
+                drop(backend);
+
+                info!(id, approve, "Reviewed held message");
+                self.ack(request_id, request).await?;
             }
             ClientMessage::ChangeName(ChangeNameRequest { new_name }) => {
+                if self.config.borrow().is_banned_name(&new_name) {
+                    return Ok(Err(
+                        ServerError::new(ErrorCode::NameNotAllowed).details(new_name)
+                    ));
+                }
+
+                let mut backend = self.backend.write().await;
+                if backend.is_name_taken(&self.uuid, &new_name) {
+                    return Ok(Err(ServerError::new(ErrorCode::NameTaken).details(new_name)));
+                }
+                backend.set_name(&self.uuid, new_name.clone());
+
+                info!(%new_name, "Changed name");
                 self.name = new_name;
+
+                drop(backend);
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::Register(CredentialsRequest { name, password }) => {
+                if self.config.borrow().is_banned_name(&name) {
+                    return Ok(Err(
+                        ServerError::new(ErrorCode::NameNotAllowed).details(name)
+                    ));
+                }
+
+                // Hashing is slow on purpose, so it runs outside the backend lock
+                let hash = tokio::task::spawn_blocking(move || accounts::hash_password(&password))
+                    .await
+                    .into_diagnostic()??;
+
+                // Whoever uses the name right now would pass as the owner of the identity
+                let mut backend = self.backend.write().await;
+                if backend.is_name_used(&self.uuid, &name) {
+                    return Ok(Err(ServerError::new(ErrorCode::NameInUse).details(name)));
+                }
+                let result = backend.accounts_mut().create(name.clone(), hash);
+                drop(backend);
+                if let Err(error) = result {
+                    return Ok(Err(error));
+                }
+
+                info!(identity = %name, "Registered identity");
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::Login(CredentialsRequest { name, password }) => {
+                let hash = self.backend.read().await.accounts().password_hash(&name);
+                let verified = match hash {
+                    Some(hash) => tokio::task::spawn_blocking(move || {
+                        accounts::verify_password(&password, &hash)
+                    })
+                    .await
+                    .into_diagnostic()?,
+                    None => false,
+                };
+                if !verified {
+                    warn!(identity = %name, "Failed login");
+                    return Ok(Err(ServerError::new(ErrorCode::InvalidCredentials)));
+                }
+
+                let mut backend = self.backend.write().await;
+                backend.set_identity(&self.uuid, name.clone());
+                let queued = backend.accounts_mut().take_queued(&name);
+                self.ignored = backend.accounts().ignored(&name);
+                drop(backend);
+
+                info!(identity = %name, "Logged in");
+                self.name = name.clone();
+                self.identity = Some(name);
+                self.ack(request_id, request).await?;
+
+                // Messages queued before their sender was ignored are dropped as well
+                for mut message in queued {
+                    if self.is_ignoring(message.from_identity.as_deref()) {
+                        continue;
+                    }
+
+                    message.queued = true;
+                    self.reply(None, ServerMessage::DirectMessage(message))
+                        .await?;
+                }
+            }
+            ClientMessage::MarkRead(MarkReadRequest { room }) => {
+                let Some(identity) = &self.identity else {
+                    return Ok(Err(ServerError::new(ErrorCode::NotLoggedIn)));
+                };
+
+                let mut backend = self.backend.write().await;
+                let last_id = match backend.get_room_mut(&room) {
+                    Ok(chat_room) => chat_room.last_id(),
+                    Err(error) => return Ok(Err(error)),
+                };
+                if let Some(id) = last_id {
+                    backend.accounts_mut().mark_read(identity, &room, id);
+                }
+                drop(backend);
+
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::Ignore(IgnoreRequest { user })
+            | ClientMessage::Unignore(IgnoreRequest { user }) => {
+                let Some(identity) = &self.identity else {
+                    return Ok(Err(ServerError::new(ErrorCode::NotLoggedIn)));
+                };
+                if user == *identity {
+                    return Ok(Err(ServerError::new(ErrorCode::InvalidMessage)
+                        .details("you cannot ignore yourself")));
+                }
+
+                // Names of anonymous users change freely, only identities can be ignored
+                let ignore = request == RequestKind::Ignore;
+                let mut backend = self.backend.write().await;
+                if ignore && !backend.accounts().is_registered(&user) {
+                    return Ok(Err(ServerError::new(ErrorCode::UserNotFound)
+                        .details(format!("{user} is not a registered identity"))));
+                }
+                backend.accounts_mut().set_ignored(identity, &user, ignore);
+                drop(backend);
+                if ignore {
+                    self.ignored.insert(user);
+                } else {
+                    self.ignored.remove(&user);
+                }
+
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::PublishKey(PublishKeyRequest { public_key }) => {
+                let Some(identity) = &self.identity else {
+                    return Ok(Err(ServerError::new(ErrorCode::NotLoggedIn)));
+                };
+
+                self.backend
+                    .write()
+                    .await
+                    .accounts_mut()
+                    .set_public_key(identity, public_key);
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::FetchKey(FetchKeyRequest { user }) => {
+                let public_key = self.backend.read().await.accounts().public_key(&user);
+                let Some(public_key) = public_key else {
+                    return Ok(Err(ServerError::new(ErrorCode::KeyNotFound).details(user)));
+                };
+
+                let server_msg = ServerMessage::PublicKey(PublicKeyResponse::new(user, public_key));
+                self.reply(request_id, server_msg).await?;
+            }
+            ClientMessage::ListIgnored() => {
+                let users = self.ignored.iter().cloned().collect();
+                let server_msg = ServerMessage::Ignored(IgnoredResponse::new(users));
+                self.reply(request_id, server_msg).await?;
+            }
+            ClientMessage::AdminLogin(AdminLoginRequest { password }) => {
+                if !self.config.borrow().is_admin_password(&password) {
+                    warn!("Failed admin login");
+                    return Ok(Err(ServerError::new(ErrorCode::InvalidPassword)));
+                }
+
+                info!("Logged in as admin");
+                self.is_admin = true;
+                self.ack(request_id, request).await?;
+            }
+            ClientMessage::Announce(AnnounceRequest { content }) => {
+                if !self.is_admin {
+                    return Ok(Err(ServerError::new(ErrorCode::NotAdmin)));
+                }
+
+                let reached = self.backend.read().await.announce(&content);
+                info!(reached, "Sent announcement");
+                self.ack(request_id, request).await?;
             }
             _ => {}
         };
 
-        Ok(())
+        Ok(Ok(()))
+    }
+
+    /// Adds or removes a reaction of the client to a message in the current chat room
+    async fn react(
+        &mut self,
+        request_id: Option<RequestId>,
+        request: RequestKind,
+        message_id: MessageId,
+        emoji: String,
+        add: bool,
+    ) -> Result<Result<(), ServerError>> {
+        let Some(room) = &self.room else {
+            return Ok(Err(ServerError::new(ErrorCode::RoomNotFound)));
+        };
+
+        let result = self
+            .backend
+            .write()
+            .await
+            .get_room_mut(room)
+            .and_then(|chat_room| chat_room.react(message_id, emoji, self.uuid, add));
+        if let Err(error) = result {
+            return Ok(Err(error));
+        }
+
+        self.ack(request_id, request).await?;
+        Ok(Ok(()))
+    }
+
+    /// Sends a stored attachment to the client in chunks
+    async fn download(
+        &mut self,
+        request_id: Option<RequestId>,
+        attachment_id: AttachmentId,
+    ) -> Result<Result<(), ServerError>> {
+        let Some(blobs) = self.backend.read().await.blobs() else {
+            return Ok(Err(ServerError::new(ErrorCode::AttachmentsDisabled)));
+        };
+
+        // Only checksums are used as paths, anything else could leave the blob directory
+        if !BlobStore::is_valid_id(&attachment_id) {
+            return Ok(Err(
+                ServerError::new(ErrorCode::AttachmentNotFound).details(attachment_id)
+            ));
+        }
+
+        let mut file = match File::open(blobs.path(&attachment_id)).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Err(
+                    ServerError::new(ErrorCode::AttachmentNotFound).details(attachment_id)
+                ));
+            }
+            Err(err) => return Err(err).into_diagnostic(),
+        };
+        let size = file.metadata().await.into_diagnostic()?.len();
+
+        // Empty files are sent as a single empty chunk
+        let mut offset = 0;
+        loop {
+            let mut data = vec![0; CHUNK_SIZE];
+            let read = file.read(&mut data).await.into_diagnostic()?;
+            data.truncate(read);
+
+            let chunk = DownloadChunkResponse::new(attachment_id.clone(), offset, size, data);
+            self.reply(request_id, ServerMessage::DownloadChunk(chunk))
+                .await?;
+
+            offset += read as u64;
+            if read == 0 || offset >= size {
+                break;
+            }
+        }
+
+        Ok(Ok(()))
     }
 
     /// Handles messages from the connected chat room
-    async fn handle_room_msg(&mut self, msg: ChatMessage) -> Result<()> {
-        if msg.sender_uuid != self.uuid.to_string() {
-            let server_msg =
-                ServerMessage::NewMessage(NewMessageRequest::new(msg.content, msg.sender_name));
-            self.ws_send
-                .send(serialize_server_msg(server_msg)?)
-                .await
-                .into_diagnostic()?;
+    async fn handle_room_msg(&mut self, msg: Broadcast) -> Result<()> {
+        // Nothing of ignored users is delivered
+        if self.is_ignoring(msg.sender_identity()) {
+            return Ok(());
         }
 
-        Ok(())
+        let server_msg = match msg {
+            Broadcast::Message(msg) if msg.sender_uuid != self.uuid.to_string() => {
+                let mut new_message = NewMessageRequest::from(msg);
+                new_message.mentioned = new_message.mentions.contains(&self.name)
+                    && self.presence != Presence::DoNotDisturb;
+                ServerMessage::NewMessage(new_message)
+            }
+            Broadcast::Message(_) => return Ok(()),
+            // Reactions are also sent back to the client that changed them
+            Broadcast::Reactions(update) => ServerMessage::Reactions(update),
+            Broadcast::Typing(typing) if typing.user_name != self.name => {
+                ServerMessage::Typing(typing)
+            }
+            Broadcast::Typing(_) => return Ok(()),
+            Broadcast::Presence(update) if update.user_name != self.name => {
+                ServerMessage::Presence(update)
+            }
+            Broadcast::Presence(_) => return Ok(()),
+        };
+
+        send_server_msg_over_socket(&mut self.ws_send, None, server_msg).await
+    }
+
+    /// Handles instructions from the server, returns a reason if the handler has to stop
+    async fn handle_command(&mut self, cmd: HandlerCommand) -> Result<Option<Disconnect>> {
+        match cmd {
+            HandlerCommand::Announce(content) => {
+                let server_msg = ServerMessage::Announcement(AnnouncementResponse::new(content));
+                send_server_msg_over_socket(&mut self.ws_send, None, server_msg).await?;
+            }
+            HandlerCommand::DirectMessage(message)
+                if self.is_ignoring(message.from_identity.as_deref()) => {}
+            HandlerCommand::DirectMessage(message) => {
+                let server_msg = ServerMessage::DirectMessage(message);
+                send_server_msg_over_socket(&mut self.ws_send, None, server_msg).await?;
+            }
+            HandlerCommand::RoomDeleted(name) if self.room.as_ref() == Some(&name) => {
+                self.hooks
+                    .room_event(&self.session(), RoomEvent::Left(&name));
+
+                // Fall back to a private channel, like a freshly connected client
+                let (room_send, room_recv) = broadcast::channel(1);
+                self.room_send = room_send;
+                self.room_recv = room_recv;
+                self.room = None;
+                self.backend.write().await.set_room(&self.uuid, None);
+
+                info!(room = %name, "Chat room was deleted");
+                let server_msg = ServerMessage::LeftChatRoom(LeftChatRoomResponse::new(name));
+                send_server_msg_over_socket(&mut self.ws_send, None, server_msg).await?;
+            }
+            HandlerCommand::RoomDeleted(_) => {}
+            HandlerCommand::Disconnect => {
+                let server_msg = ServerMessage::Err(ServerError::new(ErrorCode::Kicked));
+                send_server_msg_over_socket(&mut self.ws_send, None, server_msg).await?;
+                self.ws_send.close().await.into_diagnostic()?;
+
+                return Ok(Some(Disconnect::Kicked));
+            }
+        }
+
+        Ok(None)
     }
 
     /// Starts a handler
-    async fn run(mut self) -> Result<()> {
-        println!("Started handler {}", self.uuid);
+    async fn run(mut self) -> Result<Disconnect> {
+        info!("Started handler");
+
+        let result = self.serve().await;
+
+        if let Some(room) = &self.room {
+            self.hooks
+                .room_event(&self.session(), RoomEvent::Left(room));
+
+            let update = PresenceResponse::new(
+                self.name.clone(),
+                self.identity.clone(),
+                Presence::Offline,
+                None,
+            );
+            self.broadcast(Broadcast::Presence(update));
+        }
+
+        for (_, upload) in self.uploads.drain() {
+            upload.abort().await;
+        }
+
+        result
+    }
 
+    /// Serves the client until it disconnects
+    async fn serve(&mut self) -> Result<Disconnect> {
         loop {
-            tokio::select! {
-                Some(Ok(msg)) = self.ws_recv.next() => {
-                    self.handle_client_msg(msg).await?;
-                },
-                Ok(msg) = self.room_recv.recv() => {
-                    self.handle_room_msg(msg).await?;
+            // A new span per step shows the name and chat room the client has at that moment
+            let room = self.room.as_deref().map(field::display);
+            let span = info_span!("client", nickname = %self.name, room);
+            if let Some(reason) = self.step().instrument(span).await? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Handles the next message of the client, its chat room or the server, returns why the
+    /// handler stops if it does
+    async fn step(&mut self) -> Result<Option<Disconnect>> {
+        tokio::select! {
+            msg = self.ws_recv.next() => match msg {
+                Some(Ok(Message::Close(_))) | None => return Ok(Some(Disconnect::ClientClosed)),
+                Some(Ok(msg)) => self.handle_client_msg(msg).await?,
+                Some(Err(tungstenite::Error::Capacity(err))) => {
+                    let error = ServerError::new(ErrorCode::FrameTooLarge).details(err.to_string());
+                    self.reject(None, error).await?;
+                    self.ws_send.close().await.into_diagnostic()?;
+
+                    return Ok(Some(Disconnect::FrameTooLarge));
+                }
+                Some(Err(err)) => return Err(err).into_diagnostic(),
+            },
+            msg = self.room_recv.recv() => match msg {
+                Ok(msg) => self.handle_room_msg(msg).await?,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Fell behind the chat room");
+                    self.metrics.lagged(skipped);
+                }
+                Err(err) => warn!(error = %err, "Could not receive from chat room"),
+            },
+            Ok(()) = self.config.changed() => {
+                if self.is_banned() {
+                    let server_msg = ServerMessage::Err(ServerError::new(ErrorCode::Banned));
+                    send_server_msg_over_socket(&mut self.ws_send, None, server_msg).await?;
+                    self.ws_send.close().await.into_diagnostic()?;
+
+                    return Ok(Some(Disconnect::Banned));
                 }
             }
+            Some(cmd) = self.control.recv() => return self.handle_command(cmd).await,
         }
+
+        Ok(None)
     }
 }
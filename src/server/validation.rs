@@ -0,0 +1,360 @@
+use crate::server::{
+    blobs::BlobStore,
+    communication::{
+        client::{
+            AnnounceRequest, ChangeNameRequest, ClientMakeChatRoomRequest, ClientMessage,
+            CredentialsRequest, DirectMessageRequest, FetchHistoryRequest, FetchKeyRequest,
+            FilterSettings, IgnoreRequest, MarkReadRequest, Presence, ReactRequest, SearchRequest,
+            SendMessageRequest, SetFilterRequest, SetPresenceRequest, StartUploadRequest,
+            UploadChunkRequest, WhoRequest, CHUNK_SIZE,
+        },
+        error::{ErrorCode, ServerError},
+    },
+    config::Limits,
+    search,
+};
+
+/// Maximum number of characters in a reaction, enough for emoji sequences like flags
+const MAX_EMOJI_LEN: usize = 16;
+
+/// Maximum number of characters in a password, hashing longer ones only costs time
+const MAX_PASSWORD_LEN: usize = 128;
+
+/// Bytes ChaCha20-Poly1305 adds to the content of a sealed direct message
+const SEAL_TAG_LEN: usize = 16;
+
+/// Maximum number of characters in the status message shown next to a presence
+const MAX_STATUS_LEN: usize = 100;
+
+/// Maximum number of words and patterns in a content filter together, every message is
+/// matched against all of them
+const MAX_FILTER_ENTRIES: usize = 100;
+
+/// Maximum number of characters in a blocked word or pattern of a content filter
+const MAX_FILTER_ENTRY_LEN: usize = 200;
+
+/// Checks a piece of client input against a maximum length and the allowed characters
+fn check(
+    field: &str,
+    input: &str,
+    max_len: usize,
+    allowed: impl Fn(char) -> bool,
+) -> Result<(), ServerError> {
+    if input.trim().is_empty() {
+        return Err(ServerError::new(ErrorCode::EmptyInput).details(field));
+    }
+
+    if input.chars().count() > max_len {
+        return Err(ServerError::new(ErrorCode::InputTooLong)
+            .details(format!("{field} may be at most {max_len} characters")));
+    }
+
+    if !input.chars().all(allowed) {
+        return Err(ServerError::new(ErrorCode::InvalidCharacters).details(field));
+    }
+
+    Ok(())
+}
+
+/// File names contain no control characters or path separators
+fn is_file_name_char(c: char) -> bool {
+    !c.is_control() && c != '/' && c != '\\'
+}
+
+/// Chat messages may span multiple lines, but contain no other control characters
+fn is_content_char(c: char) -> bool {
+    !c.is_control() || c == '\n' || c == '\t'
+}
+
+/// Names of users and rooms contain no control characters at all
+fn is_name_char(c: char) -> bool {
+    !c.is_control()
+}
+
+/// Reactions are a single word
+fn is_emoji_char(c: char) -> bool {
+    !c.is_control() && !c.is_whitespace()
+}
+
+/// Checks the user input of a client message against the limits of the server
+pub fn validate(message: &ClientMessage, limits: &Limits) -> Result<(), ServerError> {
+    match message {
+        ClientMessage::SendMessage(SendMessageRequest { content, .. })
+        | ClientMessage::Announce(AnnounceRequest { content }) => {
+            check("content", content, limits.max_content_len, is_content_char)
+        }
+        ClientMessage::Ignore(IgnoreRequest { user })
+        | ClientMessage::Unignore(IgnoreRequest { user })
+        | ClientMessage::FetchKey(FetchKeyRequest { user }) => {
+            check("name", user, limits.max_name_len, is_name_char)
+        }
+        ClientMessage::DirectMessage(DirectMessageRequest {
+            to,
+            content,
+            sealed,
+        }) => {
+            check("name", to, limits.max_name_len, is_name_char)?;
+
+            // Only the size of sealed content can be checked, a character takes up to 4 bytes
+            let max_sealed_len = limits.max_content_len * 4 + SEAL_TAG_LEN;
+            match sealed {
+                Some(_) if !content.is_empty() => Err(ServerError::new(ErrorCode::InvalidMessage)
+                    .details("sealed messages have no plain content")),
+                Some(sealed) if sealed.ciphertext.len() > max_sealed_len => {
+                    Err(ServerError::new(ErrorCode::InputTooLong)
+                        .details(format!("content may be at most {max_sealed_len} bytes")))
+                }
+                Some(_) => Ok(()),
+                None => check("content", content, limits.max_content_len, is_content_char),
+            }
+        }
+        ClientMessage::Register(CredentialsRequest { name, password })
+        | ClientMessage::Login(CredentialsRequest { name, password }) => {
+            check("name", name, limits.max_name_len, is_name_char)?;
+            check("password", password, MAX_PASSWORD_LEN, is_name_char)
+        }
+        ClientMessage::MarkRead(MarkReadRequest { room })
+        | ClientMessage::FetchHistory(FetchHistoryRequest { room, .. })
+        | ClientMessage::Who(WhoRequest { room }) => {
+            check("room", room, limits.max_room_len, is_name_char)
+        }
+        ClientMessage::SetPresence(SetPresenceRequest {
+            presence: Presence::Offline,
+            ..
+        }) => Err(ServerError::new(ErrorCode::InvalidMessage)
+            .details("offline is only set by the server")),
+        ClientMessage::SetPresence(SetPresenceRequest {
+            status: Some(status),
+            ..
+        }) => check("status", status, MAX_STATUS_LEN, is_name_char),
+        ClientMessage::StartUpload(StartUploadRequest {
+            file_name,
+            size,
+            checksum,
+            ..
+        }) => {
+            check(
+                "file name",
+                file_name,
+                limits.max_name_len,
+                is_file_name_char,
+            )?;
+
+            if *size > limits.max_attachment_size {
+                return Err(
+                    ServerError::new(ErrorCode::AttachmentTooLarge).details(format!(
+                        "attachments may be at most {} bytes",
+                        limits.max_attachment_size
+                    )),
+                );
+            }
+
+            if !BlobStore::is_valid_id(checksum) {
+                return Err(ServerError::new(ErrorCode::InvalidUpload)
+                    .details("checksum has to be a hex encoded SHA-256 hash"));
+            }
+
+            Ok(())
+        }
+        ClientMessage::UploadChunk(UploadChunkRequest { data, .. }) if data.len() > CHUNK_SIZE => {
+            Err(ServerError::new(ErrorCode::InvalidUpload)
+                .details(format!("chunks may be at most {CHUNK_SIZE} bytes")))
+        }
+        ClientMessage::Search(SearchRequest {
+            room,
+            query,
+            author,
+            since,
+            until,
+            ..
+        }) => {
+            check("room", room, limits.max_room_len, is_name_char)?;
+
+            // Without words, the history is searched by author and time only
+            if query.is_empty() {
+                return if author.is_none() && since.is_none() && until.is_none() {
+                    Err(ServerError::new(ErrorCode::InvalidRequest)
+                        .details("search needs words, an author or a time"))
+                } else {
+                    Ok(())
+                };
+            }
+
+            check("query", query, limits.max_content_len, is_name_char)?;
+            if search::words(query).next().is_none() {
+                return Err(
+                    ServerError::new(ErrorCode::InvalidRequest).details("query contains no words")
+                );
+            }
+
+            Ok(())
+        }
+        ClientMessage::SetFilter(SetFilterRequest {
+            filter: Some(FilterSettings {
+                words, patterns, ..
+            }),
+        }) => {
+            if words.len() + patterns.len() > MAX_FILTER_ENTRIES {
+                return Err(ServerError::new(ErrorCode::InputTooLong).details(format!(
+                    "filters may have at most {MAX_FILTER_ENTRIES} words and patterns"
+                )));
+            }
+
+            for word in words {
+                check("filter word", word, MAX_FILTER_ENTRY_LEN, is_name_char)?;
+            }
+            for pattern in patterns {
+                check(
+                    "filter pattern",
+                    pattern,
+                    MAX_FILTER_ENTRY_LEN,
+                    is_name_char,
+                )?;
+            }
+
+            Ok(())
+        }
+        ClientMessage::MakeChatRoom(ClientMakeChatRoomRequest { name }) => {
+            check("room", name, limits.max_room_len, is_name_char)
+        }
+        ClientMessage::ChangeName(ChangeNameRequest { new_name }) => {
+            check("name", new_name, limits.max_name_len, is_name_char)
+        }
+        ClientMessage::React(ReactRequest { emoji, .. })
+        | ClientMessage::Unreact(ReactRequest { emoji, .. }) => {
+            check("emoji", emoji, MAX_EMOJI_LEN, is_emoji_char)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::communication::client::FilterMode;
+
+    fn code(message: ClientMessage) -> Option<ErrorCode> {
+        validate(&message, &Limits::default())
+            .err()
+            .map(|error| error.code)
+    }
+
+    fn send(content: &str) -> ClientMessage {
+        ClientMessage::SendMessage(SendMessageRequest::new(content.to_string()))
+    }
+
+    fn filter(words: Vec<String>, patterns: Vec<String>) -> ClientMessage {
+        ClientMessage::SetFilter(SetFilterRequest::new(Some(FilterSettings::new(
+            FilterMode::Reject,
+            words,
+            patterns,
+        ))))
+    }
+
+    #[test]
+    fn content_up_to_the_limit_is_accepted() {
+        let max = Limits::default().max_content_len;
+        assert_eq!(code(send(&"a".repeat(max))), None);
+        assert_eq!(code(send(&"ä".repeat(max))), None);
+        assert_eq!(
+            code(send(&"a".repeat(max + 1))),
+            Some(ErrorCode::InputTooLong)
+        );
+    }
+
+    #[test]
+    fn empty_and_whitespace_input_is_refused() {
+        assert_eq!(code(send("")), Some(ErrorCode::EmptyInput));
+        assert_eq!(code(send(" \n\t ")), Some(ErrorCode::EmptyInput));
+        assert_eq!(
+            code(ClientMessage::ChangeName(ChangeNameRequest::new(
+                " ".into()
+            ))),
+            Some(ErrorCode::EmptyInput)
+        );
+    }
+
+    #[test]
+    fn content_may_span_lines_but_has_no_other_control_characters() {
+        assert_eq!(code(send("one\ntwo\tthree")), None);
+        assert_eq!(code(send("one\r\ntwo")), Some(ErrorCode::InvalidCharacters));
+        assert_eq!(code(send("bell\x07")), Some(ErrorCode::InvalidCharacters));
+    }
+
+    #[test]
+    fn names_are_single_lines_up_to_the_limit() {
+        let max = Limits::default().max_name_len;
+        let name = |name: String| ClientMessage::ChangeName(ChangeNameRequest::new(name));
+        assert_eq!(code(name("a".repeat(max))), None);
+        assert_eq!(
+            code(name("a".repeat(max + 1))),
+            Some(ErrorCode::InputTooLong)
+        );
+        assert_eq!(
+            code(name("two\nlines".into())),
+            Some(ErrorCode::InvalidCharacters)
+        );
+    }
+
+    #[test]
+    fn reactions_are_single_words() {
+        let react = |emoji: &str| ClientMessage::React(ReactRequest::new(0, emoji.to_string()));
+        assert_eq!(code(react("👍")), None);
+        assert_eq!(code(react(&"x".repeat(MAX_EMOJI_LEN))), None);
+        assert_eq!(
+            code(react(&"x".repeat(MAX_EMOJI_LEN + 1))),
+            Some(ErrorCode::InputTooLong)
+        );
+        assert_eq!(code(react("a b")), Some(ErrorCode::InvalidCharacters));
+    }
+
+    #[test]
+    fn searches_need_words_or_a_filter() {
+        let search = |query: &str| SearchRequest::new("lobby".to_string(), query.to_string());
+        assert_eq!(code(ClientMessage::Search(search("hello"))), None);
+        assert_eq!(
+            code(ClientMessage::Search(search(""))),
+            Some(ErrorCode::InvalidRequest)
+        );
+        assert_eq!(
+            code(ClientMessage::Search(search("!!!"))),
+            Some(ErrorCode::InvalidRequest)
+        );
+        assert_eq!(
+            code(ClientMessage::Search(search("   "))),
+            Some(ErrorCode::EmptyInput)
+        );
+
+        let by_author = SearchRequest {
+            author: Some("alice".to_string()),
+            ..search("")
+        };
+        assert_eq!(code(ClientMessage::Search(by_author)), None);
+    }
+
+    #[test]
+    fn filters_are_limited_in_size() {
+        let words = |count: usize, len: usize| vec!["w".repeat(len); count];
+        assert_eq!(code(filter(words(MAX_FILTER_ENTRIES, 5), Vec::new())), None);
+        assert_eq!(
+            code(filter(words(MAX_FILTER_ENTRIES, 5), vec!["p".to_string()])),
+            Some(ErrorCode::InputTooLong)
+        );
+        assert_eq!(
+            code(filter(words(1, MAX_FILTER_ENTRY_LEN), Vec::new())),
+            None
+        );
+        assert_eq!(
+            code(filter(words(1, MAX_FILTER_ENTRY_LEN + 1), Vec::new())),
+            Some(ErrorCode::InputTooLong)
+        );
+        assert_eq!(
+            code(filter(Vec::new(), vec![" ".to_string()])),
+            Some(ErrorCode::EmptyInput)
+        );
+        assert_eq!(
+            code(ClientMessage::SetFilter(SetFilterRequest::new(None))),
+            None
+        );
+    }
+}